@@ -0,0 +1,7 @@
+DELETE FROM odoo_projects WHERE hula_id IS NULL;
+ALTER TABLE odoo_projects DROP COLUMN sync_status;
+ALTER TABLE odoo_projects ALTER COLUMN hula_id SET NOT NULL;
+
+DELETE FROM hubspot_projects WHERE hula_id IS NULL;
+ALTER TABLE hubspot_projects DROP COLUMN sync_status;
+ALTER TABLE hubspot_projects ALTER COLUMN hula_id SET NOT NULL;
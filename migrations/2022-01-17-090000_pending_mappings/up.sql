@@ -0,0 +1,5 @@
+ALTER TABLE odoo_projects ALTER COLUMN hula_id DROP NOT NULL;
+ALTER TABLE odoo_projects ADD COLUMN sync_status VARCHAR(20) NOT NULL DEFAULT 'synced';
+
+ALTER TABLE hubspot_projects ALTER COLUMN hula_id DROP NOT NULL;
+ALTER TABLE hubspot_projects ADD COLUMN sync_status VARCHAR(20) NOT NULL DEFAULT 'synced';
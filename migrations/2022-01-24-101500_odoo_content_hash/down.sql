@@ -0,0 +1 @@
+ALTER TABLE odoo_projects DROP COLUMN content_hash;
@@ -0,0 +1 @@
+ALTER TABLE odoo_projects ADD COLUMN content_hash VARCHAR(64) NULL;
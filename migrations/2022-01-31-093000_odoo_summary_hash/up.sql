@@ -0,0 +1 @@
+ALTER TABLE odoo_projects ADD COLUMN summary_hash VARCHAR(64) NULL;
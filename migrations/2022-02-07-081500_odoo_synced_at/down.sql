@@ -0,0 +1 @@
+ALTER TABLE odoo_projects DROP COLUMN synced_at;
@@ -0,0 +1,2 @@
+ALTER TABLE odoo_projects ADD COLUMN synced_at TIMESTAMP NULL;
+UPDATE odoo_projects SET synced_at = (now() AT TIME ZONE 'utc') WHERE sync_status = 'synced';
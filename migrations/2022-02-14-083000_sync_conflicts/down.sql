@@ -0,0 +1,2 @@
+DROP TABLE sync_conflicts;
+ALTER TABLE odoo_projects DROP COLUMN snapshot;
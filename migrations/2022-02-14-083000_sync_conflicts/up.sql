@@ -0,0 +1,23 @@
+ALTER TABLE odoo_projects ADD COLUMN snapshot TEXT NULL;
+
+CREATE TABLE sync_conflicts (
+  id UUID NOT NULL PRIMARY KEY,
+  source VARCHAR(20) NOT NULL,
+  mapping_id UUID NOT NULL,
+  crm_id VARCHAR(100) NOT NULL,
+  hula_id UUID NOT NULL,
+  field VARCHAR(50) NOT NULL,
+  crm_value TEXT NOT NULL,
+  hula_value TEXT NOT NULL,
+  status VARCHAR(20) NOT NULL,
+  inserted_by VARCHAR(100) NOT NULL,
+  inserted_at TIMESTAMP NOT NULL,
+  updated_by VARCHAR(100) NOT NULL,
+  updated_at TIMESTAMP NOT NULL,
+  updated_count SMALLINT NOT NULL
+);
+
+CREATE INDEX sync_conflicts_mapping_id ON sync_conflicts (mapping_id);
+CREATE INDEX sync_conflicts_status ON sync_conflicts (status);
+
+SELECT hula_manage_table('sync_conflicts');
@@ -0,0 +1,17 @@
+CREATE TABLE sync_retries (
+  id UUID NOT NULL PRIMARY KEY,
+  source VARCHAR(20) NOT NULL,
+  crm_id VARCHAR(100) NOT NULL,
+  attempts INTEGER NOT NULL,
+  next_attempt_at TIMESTAMP NOT NULL,
+  last_error TEXT NOT NULL,
+  inserted_by VARCHAR(100) NOT NULL,
+  inserted_at TIMESTAMP NOT NULL,
+  updated_by VARCHAR(100) NOT NULL,
+  updated_at TIMESTAMP NOT NULL,
+  updated_count SMALLINT NOT NULL
+);
+
+CREATE UNIQUE INDEX sync_retries_source_crm_id ON sync_retries (source, crm_id);
+
+SELECT hula_manage_table('sync_retries');
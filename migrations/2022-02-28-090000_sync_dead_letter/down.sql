@@ -0,0 +1,2 @@
+DROP TABLE sync_dead_letter;
+ALTER TABLE sync_retries DROP COLUMN payload;
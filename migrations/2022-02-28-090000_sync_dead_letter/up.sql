@@ -0,0 +1,20 @@
+ALTER TABLE sync_retries ADD COLUMN payload TEXT NULL;
+
+CREATE TABLE sync_dead_letter (
+  id UUID NOT NULL PRIMARY KEY,
+  source VARCHAR(20) NOT NULL,
+  crm_id VARCHAR(100) NOT NULL,
+  payload TEXT NOT NULL,
+  error TEXT NOT NULL,
+  attempts INTEGER NOT NULL,
+  status VARCHAR(20) NOT NULL,
+  inserted_by VARCHAR(100) NOT NULL,
+  inserted_at TIMESTAMP NOT NULL,
+  updated_by VARCHAR(100) NOT NULL,
+  updated_at TIMESTAMP NOT NULL,
+  updated_count SMALLINT NOT NULL
+);
+
+CREATE UNIQUE INDEX sync_dead_letter_source_crm_id ON sync_dead_letter (source, crm_id);
+
+SELECT hula_manage_table('sync_dead_letter');
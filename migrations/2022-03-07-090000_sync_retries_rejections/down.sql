@@ -0,0 +1 @@
+ALTER TABLE sync_retries DROP COLUMN rejections;
@@ -0,0 +1 @@
+ALTER TABLE sync_retries ADD COLUMN rejections INTEGER NOT NULL DEFAULT 0;
@@ -1,14 +1,15 @@
-use async_std::task;
-use diesel::prelude::*;
 use log::{error, info};
 use std::time::Duration;
 
+use crate::health::Health;
 use crate::hulautils;
+use crate::metrics;
 use crate::models::odoo_project::Pool;
 use crate::modules::hubspot::hubspot_module;
 use crate::modules::odoo::odoo_module;
+use crate::shutdown;
 
-pub async fn start_background(pool: Pool) {
+pub async fn start_background(pool: Pool, health: Health) {
 	info!("Starting background processing.");
 
 	let modules = std::env::var("MODULES").expect("MODULES must be set");
@@ -21,12 +22,21 @@ pub async fn start_background(pool: Pool) {
 
 	info!("Sleep delay: {}", &seconds);
 
+	let grace: u64 = std::env::var("SHUTDOWN_TIMEOUT")
+		.ok()
+		.and_then(|v| v.parse().ok())
+		.unwrap_or(30);
+
+	info!("Shutdown timeout: {}", &grace);
+
+	let mut shutdown = shutdown::listen();
+
 	loop {
 		info!("Processing.");
 
-		let conn: &PgConnection = &pool.get().unwrap();
-
 		let config = hulautils::get_config().await;
+		health.hula_login(config.is_ok());
+		metrics::hula_login(config.is_ok());
 		let config = match config {
 			Ok(v) => v,
 			Err(e) => {
@@ -38,35 +48,52 @@ pub async fn start_background(pool: Pool) {
 		let a = m.iter();
 
 		for s in a {
-			let mut result: Result<(), String> = Ok(());
-
-			match *s {
-				"odoo" => {
-					result = odoo_module::do_process(&config, conn).await;
-				}
-				"hubspot" => {
-					result = hubspot_module::do_process(&config, conn).await;
-				}
-				_ => error!("Unknown module defined in MODULES variable!"),
+			if shutdown::is_requested(&shutdown) {
+				info!("Shutdown requested, skipping remaining modules.");
+				break;
+			}
+
+			let process = async {
+				let result = match *s {
+					"odoo" => odoo_module::do_process(&config, &pool).await,
+					"hubspot" => hubspot_module::do_process(&config, &pool).await,
+					_ => {
+						error!("Unknown module defined in MODULES variable!");
+						Ok(0)
+					}
+				};
+				result.map_err(String::from)
 			};
 
-			match result {
-				Ok(v) => v,
-				Err(e) => {
-					error!("Failure! {}", e);
-				}
+			let started = chrono::Utc::now().naive_utc();
+			let result =
+				shutdown::run_with_grace(process, &mut shutdown, Duration::from_secs(grace)).await;
+
+			health.module_run(s, started, &result);
+			metrics::module_run(s, &result);
+
+			if let Err(e) = result {
+				error!("Failure! {}", e);
 			}
 		}
 
 		let res = hulautils::close_config(&config).await;
-		let _ = match res {
-			Ok(v) => v,
-			Err(e) => {
-				error!("NO CONNECTION to HULA: {}", &e);
+		if let Err(e) = res {
+			error!("NO CONNECTION to HULA: {}", &e);
+			return;
+		}
+
+		if shutdown::is_requested(&shutdown) {
+			info!("Background processing stopped.");
+			return;
+		}
+
+		tokio::select! {
+			_ = tokio::time::sleep(Duration::from_secs(seconds)) => {}
+			_ = shutdown::requested(&mut shutdown) => {
+				info!("Background processing stopped.");
 				return;
 			}
-		};
-
-		task::sleep(Duration::from_secs(seconds)).await;
+		}
 	}
 }
@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use uuid::Uuid;
+
+use crate::hulautils::HulaProject;
+use crate::models::sync_status;
+
+/// A CRM record (Odoo lead, HubSpot deal) that is synced to a Hula project.
+pub trait CrmRecord {
+	type Key: Hash + Eq;
+
+	fn crm_key(&self) -> Self::Key;
+}
+
+/// A row of a mapping table linking a CRM record to a Hula project.
+pub trait Mapping {
+	type Key: Hash + Eq;
+
+	fn crm_key(&self) -> Self::Key;
+	fn hula_id(&self) -> Option<Uuid>;
+	fn sync_status(&self) -> &str;
+}
+
+/// Hula projects keyed by id.
+pub struct ProjectIndex<'a> {
+	projects: HashMap<Uuid, &'a HulaProject>,
+}
+
+impl<'a> ProjectIndex<'a> {
+	pub fn new(projects: &'a [HulaProject]) -> ProjectIndex<'a> {
+		ProjectIndex {
+			projects: projects
+				.iter()
+				.filter_map(|x| Uuid::parse_str(&x.id).ok().map(|id| (id, x)))
+				.collect(),
+		}
+	}
+
+	pub fn get(&self, id: &Uuid) -> Option<&'a HulaProject> {
+		self.projects.get(id).copied()
+	}
+
+	pub fn contains(&self, id: &Uuid) -> bool {
+		self.projects.contains_key(id)
+	}
+}
+
+/// A CRM record that needs a new Hula project. `pending` is the mapping row
+/// reserved by an earlier, unfinished attempt.
+pub struct Create<'a, R, M> {
+	pub record: &'a R,
+	pub pending: Option<&'a M>,
+}
+
+/// A CRM record whose mapping points to an existing Hula project.
+pub struct Mapped<'a, R, M> {
+	pub record: &'a R,
+	pub mapping: &'a M,
+	pub project: &'a HulaProject,
+	pub hula_id: Uuid,
+}
+
+pub struct ChangeSet<'a, R, M> {
+	pub create: Vec<Create<'a, R, M>>,
+	pub update: Vec<Mapped<'a, R, M>>,
+	pub unchanged: Vec<Mapped<'a, R, M>>,
+	/// Synced mappings whose Hula project no longer exists.
+	pub orphaned: Vec<&'a M>,
+}
+
+/// Synced mappings whose Hula project is missing from `index`.
+pub fn orphaned<'a, M: Mapping>(log: &'a [M], index: &ProjectIndex) -> Vec<&'a M> {
+	log.iter()
+		.filter(|x| x.sync_status() == sync_status::SYNCED)
+		.filter(|x| match x.hula_id() {
+			Some(id) => !index.contains(&id),
+			None => false,
+		})
+		.collect()
+}
+
+/// Sorts `records` into what has to be created, updated or left alone.
+/// `needs_update` decides whether a mapped record has to be sent again.
+/// Records whose mapping was removed or orphaned are left out.
+pub fn diff<'a, R, M, F>(
+	records: &'a [R],
+	log: &'a [M],
+	projects: &'a [HulaProject],
+	needs_update: F,
+) -> ChangeSet<'a, R, M>
+where
+	R: CrmRecord,
+	M: Mapping<Key = R::Key>,
+	F: Fn(&R, &M, &HulaProject) -> bool,
+{
+	let index = ProjectIndex::new(projects);
+	let by_crm_key: HashMap<M::Key, &M> = log.iter().map(|x| (x.crm_key(), x)).collect();
+
+	let mut changes = ChangeSet {
+		create: vec![],
+		update: vec![],
+		unchanged: vec![],
+		orphaned: orphaned(log, &index),
+	};
+
+	for record in records {
+		let mapping = match by_crm_key.get(&record.crm_key()) {
+			Some(mapping) => *mapping,
+			None => {
+				changes.create.push(Create {
+					record,
+					pending: None,
+				});
+				continue;
+			}
+		};
+
+		if mapping.sync_status() == sync_status::PENDING {
+			changes.create.push(Create {
+				record,
+				pending: Some(mapping),
+			});
+			continue;
+		}
+
+		if mapping.sync_status() != sync_status::SYNCED {
+			continue;
+		}
+
+		let hula_id = match mapping.hula_id() {
+			Some(id) => id,
+			None => continue,
+		};
+
+		let project = match index.get(&hula_id) {
+			Some(project) => project,
+			None => continue,
+		};
+
+		let mapped = Mapped {
+			record,
+			mapping,
+			project,
+			hula_id,
+		};
+
+		if needs_update(record, mapping, project) {
+			changes.update.push(mapped);
+		} else {
+			changes.unchanged.push(mapped);
+		}
+	}
+
+	changes
+}
+
+/// A mapping row for tests of code that is generic over `Mapping`.
+#[cfg(test)]
+pub mod testing {
+	use super::*;
+
+	pub struct Row {
+		pub crm: i32,
+		pub hula: Option<Uuid>,
+		pub status: &'static str,
+	}
+
+	impl Mapping for Row {
+		type Key = i32;
+
+		fn crm_key(&self) -> i32 {
+			self.crm
+		}
+
+		fn hula_id(&self) -> Option<Uuid> {
+			self.hula
+		}
+
+		fn sync_status(&self) -> &str {
+			self.status
+		}
+	}
+
+	/// A synced row mapping CRM record `crm` to `hula`.
+	pub fn row(crm: i32, hula: Option<Uuid>) -> Row {
+		Row {
+			crm,
+			hula,
+			status: sync_status::SYNCED,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::testing::{row, Row};
+	use super::*;
+
+	struct Lead {
+		id: i32,
+		changed: bool,
+	}
+
+	impl CrmRecord for Lead {
+		type Key = i32;
+
+		fn crm_key(&self) -> i32 {
+			self.id
+		}
+	}
+
+	fn project(id: Uuid) -> HulaProject {
+		HulaProject {
+			id: id.to_string(),
+			description: None,
+			name: "Portal".to_string(),
+			external_id: None,
+			updated_at: None,
+		}
+	}
+
+	#[test]
+	fn diff_sorts_records() {
+		let (updated, unchanged, missing, removed) = (
+			Uuid::new_v4(),
+			Uuid::new_v4(),
+			Uuid::new_v4(),
+			Uuid::new_v4(),
+		);
+		let leads: Vec<Lead> = (1..=6)
+			.map(|id| Lead {
+				id,
+				changed: id != 3,
+			})
+			.collect();
+		let log = [
+			row(2, Some(updated)),
+			row(3, Some(unchanged)),
+			row(4, Some(missing)),
+			Row {
+				status: sync_status::PENDING,
+				..row(5, None)
+			},
+			Row {
+				status: sync_status::REMOVED,
+				..row(6, Some(removed))
+			},
+		];
+		let projects = [project(updated), project(unchanged), project(removed)];
+
+		let changes = diff(&leads, &log, &projects, |lead, _, _| lead.changed);
+
+		let create: Vec<_> = changes
+			.create
+			.iter()
+			.map(|x| (x.record.id, x.pending.is_some()))
+			.collect();
+		assert_eq!(create, vec![(1, false), (5, true)]);
+
+		let update: Vec<_> = changes.update.iter().map(|x| x.record.id).collect();
+		assert_eq!(update, vec![2]);
+		assert_eq!(changes.update[0].hula_id, updated);
+
+		let unchanged: Vec<_> = changes.unchanged.iter().map(|x| x.record.id).collect();
+		assert_eq!(unchanged, vec![3]);
+
+		let orphaned: Vec<_> = changes.orphaned.iter().map(|x| x.crm).collect();
+		assert_eq!(orphaned, vec![4]);
+	}
+}
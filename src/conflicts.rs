@@ -0,0 +1,278 @@
+use diesel::prelude::*;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::db;
+use crate::models::odoo_project::Pool;
+use crate::models::sync_conflict::{self, SyncConflict};
+use crate::modules::odoo::reverse_sync::ReverseConfig;
+
+/// Conflicts of `source` that are open or resolved but not yet applied.
+pub async fn unapplied(pool: &Pool, source: &str) -> Result<Vec<SyncConflict>, String> {
+	use crate::schema::sync_conflicts::dsl;
+
+	let source = source.to_string();
+	let items = db::run(pool, move |conn| {
+		dsl::sync_conflicts
+			.filter(dsl::source.eq(source))
+			.filter(dsl::status.ne(sync_conflict::APPLIED))
+			.load::<SyncConflict>(conn)
+	})
+	.await?;
+
+	match items {
+		Ok(items) => Ok(items),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+/// Stores new conflicts. A conflict already open for the same mapping and
+/// field gets the current values instead of a second row.
+pub async fn park(pool: &Pool, conflicts: Vec<SyncConflict>) -> Result<(), String> {
+	use crate::schema::sync_conflicts::dsl;
+
+	let result = db::run(pool, move |conn| {
+		for conflict in conflicts {
+			let existing = dsl::sync_conflicts
+				.filter(dsl::mapping_id.eq(conflict.mapping_id))
+				.filter(dsl::field.eq(&conflict.field))
+				.filter(dsl::status.eq(sync_conflict::OPEN))
+				.select(dsl::id)
+				.first::<Uuid>(conn)
+				.optional()?;
+
+			match existing {
+				Some(id) => {
+					diesel::update(dsl::sync_conflicts.find(id))
+						.set((
+							dsl::crm_value.eq(&conflict.crm_value),
+							dsl::hula_value.eq(&conflict.hula_value),
+						))
+						.execute(conn)?;
+				}
+				None => {
+					diesel::insert_into(dsl::sync_conflicts)
+						.values(&conflict)
+						.execute(conn)?;
+				}
+			}
+		}
+
+		Ok::<(), diesel::result::Error>(())
+	})
+	.await?;
+
+	match result {
+		Ok(_) => Ok(()),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+/// Marks resolved conflicts as written to both sides.
+pub async fn mark_applied(pool: &Pool, ids: Vec<Uuid>) -> Result<(), String> {
+	use crate::schema::sync_conflicts::dsl;
+
+	if ids.is_empty() {
+		return Ok(());
+	}
+
+	let updated = db::run(pool, move |conn| {
+		diesel::update(dsl::sync_conflicts.filter(dsl::id.eq_any(ids)))
+			.set(dsl::status.eq(sync_conflict::APPLIED))
+			.execute(conn)
+	})
+	.await?;
+
+	match updated {
+		Ok(_) => Ok(()),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+/// Sets resolved conflicts back to open.
+pub async fn reopen(pool: &Pool, ids: Vec<Uuid>) -> Result<(), String> {
+	use crate::schema::sync_conflicts::dsl;
+
+	if ids.is_empty() {
+		return Ok(());
+	}
+
+	let updated = db::run(pool, move |conn| {
+		diesel::update(dsl::sync_conflicts.filter(dsl::id.eq_any(ids)))
+			.set(dsl::status.eq(sync_conflict::OPEN))
+			.execute(conn)
+	})
+	.await?;
+
+	match updated {
+		Ok(_) => Ok(()),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+/// Picks the side whose value wins. The next sync run writes it to the
+/// other side.
+pub async fn resolve(pool: &Pool, id: Uuid, keep: &str) -> Result<(), String> {
+	use crate::schema::sync_conflicts::dsl;
+
+	let keep = match keep {
+		"crm" => sync_conflict::KEEP_CRM,
+		"hula" => sync_conflict::KEEP_HULA,
+		other => return Err(format!("Unknown side: {}, use crm or hula", other)),
+	};
+
+	let updated = db::run(pool, move |conn| {
+		diesel::update(
+			dsl::sync_conflicts
+				.find(id)
+				.filter(dsl::status.ne(sync_conflict::APPLIED)),
+		)
+		.set(dsl::status.eq(keep))
+		.execute(conn)
+	})
+	.await?;
+
+	match updated {
+		Ok(0) => Err(format!("No unapplied conflict {}", id)),
+		Ok(_) => Ok(()),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+async fn load_all(pool: &Pool, include_applied: bool) -> Result<Vec<SyncConflict>, String> {
+	use crate::schema::sync_conflicts::dsl;
+
+	let items = db::run(pool, move |conn| {
+		let mut query = dsl::sync_conflicts
+			.order(dsl::updated_at.desc())
+			.into_boxed();
+		if !include_applied {
+			query = query.filter(dsl::status.ne(sync_conflict::APPLIED));
+		}
+		query.load::<SyncConflict>(conn)
+	})
+	.await?;
+
+	match items {
+		Ok(items) => Ok(items),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+async fn find(pool: &Pool, id: Uuid) -> Result<Option<SyncConflict>, String> {
+	use crate::schema::sync_conflicts::dsl;
+
+	let item = db::run(pool, move |conn| {
+		dsl::sync_conflicts
+			.find(id)
+			.first::<SyncConflict>(conn)
+			.optional()
+	})
+	.await?;
+
+	match item {
+		Ok(item) => Ok(item),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+#[derive(Serialize)]
+struct Listing<'a> {
+	conflicts: &'a [SyncConflict],
+}
+
+fn parse_id(value: Option<&String>) -> Result<Uuid, String> {
+	match value.map(|x| Uuid::parse_str(x)) {
+		Some(Ok(id)) => Ok(id),
+		Some(Err(e)) => Err(format!("Invalid conflict id: {}", e)),
+		None => Err("Conflict id missing".to_string()),
+	}
+}
+
+/// Entry point of `hula-sync conflicts list [--all] [--json]`,
+/// `hula-sync conflicts show <id>` and
+/// `hula-sync conflicts resolve <id> crm|hula`.
+pub async fn run(pool: Pool, args: &[String]) -> Result<(), String> {
+	match args.first().map(|x| &x[..]) {
+		Some("list") => {
+			let all = args.iter().any(|x| x == "--all");
+			let conflicts = load_all(&pool, all).await?;
+
+			if args.iter().any(|x| x == "--json") {
+				let listing = Listing {
+					conflicts: &conflicts,
+				};
+				match serde_json::to_string_pretty(&listing) {
+					Ok(json) => println!("{}", json),
+					Err(e) => return Err(format!("Serde failed: {}", e)),
+				}
+				return Ok(());
+			}
+
+			if conflicts.is_empty() {
+				println!("No conflicts.");
+				return Ok(());
+			}
+
+			println!(
+				"{:<37} {:<7} {:<12} {:<10} {:<8} UPDATED",
+				"ID", "SOURCE", "CRM ID", "FIELD", "STATUS"
+			);
+
+			for conflict in &conflicts {
+				println!(
+					"{:<37} {:<7} {:<12} {:<10} {:<8} {}",
+					conflict.id,
+					&conflict.source,
+					&conflict.crm_id,
+					&conflict.field,
+					&conflict.status,
+					conflict.updated_at.format("%Y-%m-%d %H:%M")
+				);
+			}
+
+			Ok(())
+		}
+		Some("show") => {
+			let id = parse_id(args.get(1))?;
+			let conflict = match find(&pool, id).await? {
+				Some(c) => c,
+				None => return Err(format!("No conflict {}", id)),
+			};
+
+			println!("Conflict:     {}", conflict.id);
+			println!("Source:       {}", &conflict.source);
+			println!("CRM record:   {}", &conflict.crm_id);
+			println!("Hula project: {}", conflict.hula_id);
+			println!("Field:        {}", &conflict.field);
+			println!("Status:       {}", &conflict.status);
+			println!("CRM value:    {}", &conflict.crm_value);
+			println!("Hula value:   {}", &conflict.hula_value);
+
+			Ok(())
+		}
+		Some("resolve") => {
+			let id = parse_id(args.get(1))?;
+			let keep = match args.get(2) {
+				Some(v) => v,
+				None => return Err("Side missing, use crm or hula".to_string()),
+			};
+
+			if keep == "hula" && !ReverseConfig::from_env().enabled {
+				return Err(
+					"Keeping the Hula value needs ODOO_REVERSE_SYNC=true to write it to Odoo"
+						.to_string(),
+				);
+			}
+
+			resolve(&pool, id, keep).await?;
+			println!("Conflict {} resolved, applied on the next sync run.", id);
+
+			Ok(())
+		}
+		_ => Err(
+			"Usage: conflicts list [--all] [--json] | show <id> | resolve <id> crm|hula"
+				.to_string(),
+		),
+	}
+}
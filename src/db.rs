@@ -0,0 +1,24 @@
+use diesel::PgConnection;
+
+use crate::models::odoo_project::Pool;
+
+/// Runs a blocking Diesel operation on Tokio's blocking thread pool. A
+/// connection is checked out of `pool` for the duration of `f` only.
+pub async fn run<F, T>(pool: &Pool, f: F) -> Result<T, String>
+where
+	F: FnOnce(&PgConnection) -> T + Send + 'static,
+	T: Send + 'static,
+{
+	let pool = pool.clone();
+
+	let result = tokio::task::spawn_blocking(move || match pool.get() {
+		Ok(conn) => Ok(f(&conn)),
+		Err(e) => Err(format!("No database connection: {}", e)),
+	})
+	.await;
+
+	match result {
+		Ok(r) => r,
+		Err(e) => Err(format!("Database task failed: {}", e)),
+	}
+}
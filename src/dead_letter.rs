@@ -0,0 +1,332 @@
+use diesel::prelude::*;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::db;
+use crate::models::odoo_project::Pool;
+use crate::models::sync_dead_letter::{self, SyncDeadLetter};
+
+/// Rejected attempts after which a record moves to the dead-letter queue.
+/// `SYNC_MAX_ATTEMPTS`, default 5.
+pub fn max_attempts() -> i32 {
+	std::env::var("SYNC_MAX_ATTEMPTS")
+		.ok()
+		.and_then(|v| v.parse().ok())
+		.unwrap_or(5)
+}
+
+/// Dead letters of `source`, whether dead or queued for a retry.
+pub async fn load(pool: &Pool, source: &str) -> Result<Vec<SyncDeadLetter>, String> {
+	use crate::schema::sync_dead_letter::dsl;
+
+	let source = source.to_string();
+	let items = db::run(pool, move |conn| {
+		dsl::sync_dead_letter
+			.filter(dsl::source.eq(source))
+			.load::<SyncDeadLetter>(conn)
+	})
+	.await?;
+
+	match items {
+		Ok(items) => Ok(items),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+/// Moves a record from `sync_retries` to the dead-letter queue, or back
+/// there if a retry from the CLI failed too.
+pub async fn bury(
+	pool: &Pool,
+	source: &str,
+	crm_id: String,
+	payload: String,
+	error: String,
+	attempts: i32,
+) -> Result<(), String> {
+	use crate::schema::sync_dead_letter::dsl;
+	use crate::schema::sync_retries::dsl as retries;
+
+	let source = source.to_string();
+	let result = db::run(pool, move |conn| {
+		conn.transaction::<_, diesel::result::Error, _>(|| {
+			diesel::delete(
+				retries::sync_retries
+					.filter(retries::source.eq(&source))
+					.filter(retries::crm_id.eq(&crm_id)),
+			)
+			.execute(conn)?;
+
+			let existing = dsl::sync_dead_letter
+				.filter(dsl::source.eq(&source))
+				.filter(dsl::crm_id.eq(&crm_id))
+				.select(dsl::id)
+				.first::<Uuid>(conn)
+				.optional()?;
+
+			match existing {
+				Some(id) => {
+					diesel::update(dsl::sync_dead_letter.find(id))
+						.set((
+							dsl::payload.eq(payload),
+							dsl::error.eq(error),
+							dsl::attempts.eq(attempts),
+							dsl::status.eq(sync_dead_letter::DEAD),
+						))
+						.execute(conn)?;
+				}
+				None => {
+					let item = SyncDeadLetter {
+						id: Uuid::new_v4(),
+						source,
+						crm_id,
+						payload,
+						error,
+						attempts,
+						status: sync_dead_letter::DEAD.to_string(),
+						updated_by: "hulasync".to_string(),
+						updated_at: chrono::Utc::now().naive_utc(),
+					};
+					diesel::insert_into(dsl::sync_dead_letter)
+						.values(&item)
+						.execute(conn)?;
+				}
+			}
+
+			Ok(())
+		})
+	})
+	.await?;
+
+	match result {
+		Ok(_) => Ok(()),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+/// Removes the dead letters of records that have synced.
+pub async fn clear(pool: &Pool, source: &str, crm_ids: Vec<String>) -> Result<(), String> {
+	use crate::schema::sync_dead_letter::dsl;
+
+	if crm_ids.is_empty() {
+		return Ok(());
+	}
+
+	let source = source.to_string();
+	let deleted = db::run(pool, move |conn| {
+		diesel::delete(
+			dsl::sync_dead_letter
+				.filter(dsl::source.eq(source))
+				.filter(dsl::crm_id.eq_any(crm_ids)),
+		)
+		.execute(conn)
+	})
+	.await?;
+
+	match deleted {
+		Ok(_) => Ok(()),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+async fn find(pool: &Pool, id: Uuid) -> Result<Option<SyncDeadLetter>, String> {
+	use crate::schema::sync_dead_letter::dsl;
+
+	let item = db::run(pool, move |conn| {
+		dsl::sync_dead_letter
+			.find(id)
+			.first::<SyncDeadLetter>(conn)
+			.optional()
+	})
+	.await?;
+
+	match item {
+		Ok(item) => Ok(item),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+async fn load_all(pool: &Pool) -> Result<Vec<SyncDeadLetter>, String> {
+	use crate::schema::sync_dead_letter::dsl;
+
+	let items = db::run(pool, move |conn| {
+		dsl::sync_dead_letter
+			.order(dsl::updated_at.desc())
+			.load::<SyncDeadLetter>(conn)
+	})
+	.await?;
+
+	match items {
+		Ok(items) => Ok(items),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+/// Queues a dead letter for the next sync run, with `payload` replacing
+/// the one that failed if given.
+async fn queue_retry(pool: &Pool, id: Uuid, payload: Option<String>) -> Result<(), String> {
+	use crate::schema::sync_dead_letter::dsl;
+
+	let updated = db::run(pool, move |conn| {
+		let target = dsl::sync_dead_letter.find(id);
+		match payload {
+			Some(payload) => diesel::update(target)
+				.set((
+					dsl::status.eq(sync_dead_letter::EDITED),
+					dsl::payload.eq(payload),
+				))
+				.execute(conn),
+			None => diesel::update(target)
+				.set(dsl::status.eq(sync_dead_letter::RETRY))
+				.execute(conn),
+		}
+	})
+	.await?;
+
+	match updated {
+		Ok(0) => Err(format!("No dead letter {}", id)),
+		Ok(_) => Ok(()),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+async fn discard(pool: &Pool, id: Uuid) -> Result<(), String> {
+	use crate::schema::sync_dead_letter::dsl;
+
+	let deleted = db::run(pool, move |conn| {
+		diesel::delete(dsl::sync_dead_letter.find(id)).execute(conn)
+	})
+	.await?;
+
+	match deleted {
+		Ok(0) => Err(format!("No dead letter {}", id)),
+		Ok(_) => Ok(()),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+#[derive(Serialize)]
+struct Listing<'a> {
+	dead_letters: &'a [SyncDeadLetter],
+}
+
+fn parse_id(value: Option<&String>) -> Result<Uuid, String> {
+	match value.map(|x| Uuid::parse_str(x)) {
+		Some(Ok(id)) => Ok(id),
+		Some(Err(e)) => Err(format!("Invalid dead letter id: {}", e)),
+		None => Err("Dead letter id missing".to_string()),
+	}
+}
+
+/// Reads an edited payload for `retry --payload <file>`. It has to be JSON,
+/// its shape is checked by the sync run that sends it.
+fn read_payload(path: &str) -> Result<String, String> {
+	let text = match std::fs::read_to_string(path) {
+		Ok(v) => v,
+		Err(e) => return Err(format!("Reading {} failed: {}", path, e)),
+	};
+
+	match serde_json::from_str::<serde_json::Value>(&text) {
+		Ok(v) => Ok(v.to_string()),
+		Err(e) => Err(format!("{} is not valid JSON: {}", path, e)),
+	}
+}
+
+/// Entry point of `hula-sync deadletter list [--json]`,
+/// `hula-sync deadletter show <id>`,
+/// `hula-sync deadletter retry <id> [--payload <file>]` and
+/// `hula-sync deadletter discard <id>`.
+pub async fn run(pool: Pool, args: &[String]) -> Result<(), String> {
+	match args.first().map(|x| &x[..]) {
+		Some("list") => {
+			let letters = load_all(&pool).await?;
+
+			if args.iter().any(|x| x == "--json") {
+				let listing = Listing {
+					dead_letters: &letters,
+				};
+				match serde_json::to_string_pretty(&listing) {
+					Ok(json) => println!("{}", json),
+					Err(e) => return Err(format!("Serde failed: {}", e)),
+				}
+				return Ok(());
+			}
+
+			if letters.is_empty() {
+				println!("No dead letters.");
+				return Ok(());
+			}
+
+			println!(
+				"{:<37} {:<7} {:<12} {:<8} {:<6} ERROR",
+				"ID", "SOURCE", "CRM ID", "STATUS", "TRIES"
+			);
+
+			for letter in &letters {
+				println!(
+					"{:<37} {:<7} {:<12} {:<8} {:<6} {}",
+					letter.id,
+					&letter.source,
+					&letter.crm_id,
+					&letter.status,
+					letter.attempts,
+					letter.error.lines().next().unwrap_or_default()
+				);
+			}
+
+			Ok(())
+		}
+		Some("show") => {
+			let id = parse_id(args.get(1))?;
+			let letter = match find(&pool, id).await? {
+				Some(l) => l,
+				None => return Err(format!("No dead letter {}", id)),
+			};
+
+			let payload = serde_json::from_str::<serde_json::Value>(&letter.payload)
+				.ok()
+				.and_then(|x| serde_json::to_string_pretty(&x).ok())
+				.unwrap_or_else(|| letter.payload.clone());
+
+			println!("Dead letter: {}", letter.id);
+			println!("Source:      {}", &letter.source);
+			println!("CRM record:  {}", &letter.crm_id);
+			println!("Status:      {}", &letter.status);
+			println!("Attempts:    {}", letter.attempts);
+			println!("Updated:     {}", letter.updated_at.format("%Y-%m-%d %H:%M"));
+			println!("Error:       {}", &letter.error);
+			println!("Payload:\n{}", payload);
+
+			Ok(())
+		}
+		Some("retry") => {
+			let id = parse_id(args.get(1))?;
+			let payload = match args.iter().position(|x| x == "--payload") {
+				Some(idx) => match args.get(idx + 1) {
+					Some(path) => Some(read_payload(path)?),
+					None => return Err("--payload needs a file".to_string()),
+				},
+				None => None,
+			};
+
+			queue_retry(&pool, id, payload).await?;
+			println!("Dead letter {} queued, retried on the next sync run.", id);
+
+			Ok(())
+		}
+		Some("discard") => {
+			let id = parse_id(args.get(1))?;
+
+			discard(&pool, id).await?;
+			println!(
+				"Dead letter {} discarded. The record syncs again once it is fetched.",
+				id
+			);
+
+			Ok(())
+		}
+		_ => Err(
+			"Usage: deadletter list [--json] | show <id> | retry <id> [--payload <file>] | discard <id>"
+				.to_string(),
+		),
+	}
+}
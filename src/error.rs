@@ -0,0 +1,111 @@
+/// Why a sync step failed. Database, HTTP, JSON and id errors convert with
+/// `?`, so a failing step is logged by the run instead of panicking.
+#[derive(Debug)]
+pub enum SyncError {
+	/// A required environment variable is not set.
+	Config(&'static str),
+	Database(diesel::result::Error),
+	Http(reqwest::Error),
+	/// A response body was not what the service was expected to send.
+	Decode(reqwest::Error),
+	/// A service answered with an error status.
+	Status(reqwest::StatusCode),
+	Json(serde_json::Error),
+	InvalidId(uuid::Error),
+	Other(String),
+}
+
+impl SyncError {
+	/// Whether the service rejected the request itself, so sending it again
+	/// unchanged will fail the same way. Timeouts and rate limits are not.
+	pub fn is_permanent(&self) -> bool {
+		match self {
+			SyncError::Status(status) => {
+				status.is_client_error()
+					&& *status != reqwest::StatusCode::REQUEST_TIMEOUT
+					&& *status != reqwest::StatusCode::TOO_MANY_REQUESTS
+			}
+			_ => false,
+		}
+	}
+}
+
+impl std::fmt::Display for SyncError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			SyncError::Config(name) => write!(f, "{} must be set", name),
+			SyncError::Database(e) => write!(f, "Database error: {}", e),
+			SyncError::Http(e) => write!(f, "HTTP request failed: {}", e),
+			SyncError::Decode(e) => write!(f, "Response could not be decoded: {}", e),
+			SyncError::Status(status) => write!(f, "Request failed with status {}", status),
+			SyncError::Json(e) => write!(f, "JSON was not well-formatted: {}", e),
+			SyncError::InvalidId(e) => write!(f, "Invalid id: {}", e),
+			SyncError::Other(e) => write!(f, "{}", e),
+		}
+	}
+}
+
+impl std::error::Error for SyncError {}
+
+impl From<diesel::result::Error> for SyncError {
+	fn from(error: diesel::result::Error) -> SyncError {
+		SyncError::Database(error)
+	}
+}
+
+impl From<reqwest::Error> for SyncError {
+	fn from(error: reqwest::Error) -> SyncError {
+		SyncError::Http(error)
+	}
+}
+
+impl From<serde_json::Error> for SyncError {
+	fn from(error: serde_json::Error) -> SyncError {
+		SyncError::Json(error)
+	}
+}
+
+impl From<uuid::Error> for SyncError {
+	fn from(error: uuid::Error) -> SyncError {
+		SyncError::InvalidId(error)
+	}
+}
+
+impl From<String> for SyncError {
+	fn from(error: String) -> SyncError {
+		SyncError::Other(error)
+	}
+}
+
+impl From<&'static str> for SyncError {
+	fn from(error: &'static str) -> SyncError {
+		SyncError::Other(error.to_string())
+	}
+}
+
+impl From<SyncError> for String {
+	fn from(error: SyncError) -> String {
+		error.to_string()
+	}
+}
+
+/// Reads a required environment variable.
+pub fn env(name: &'static str) -> Result<String, SyncError> {
+	std::env::var(name).map_err(|_| SyncError::Config(name))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use reqwest::StatusCode;
+
+	#[test]
+	fn only_rejected_requests_are_permanent() {
+		assert!(SyncError::Status(StatusCode::BAD_REQUEST).is_permanent());
+		assert!(SyncError::Status(StatusCode::UNPROCESSABLE_ENTITY).is_permanent());
+		assert!(!SyncError::Status(StatusCode::TOO_MANY_REQUESTS).is_permanent());
+		assert!(!SyncError::Status(StatusCode::REQUEST_TIMEOUT).is_permanent());
+		assert!(!SyncError::Status(StatusCode::BAD_GATEWAY).is_permanent());
+		assert!(!SyncError::Other("connection reset".to_string()).is_permanent());
+	}
+}
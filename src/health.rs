@@ -0,0 +1,196 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::{error, info};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::db;
+use crate::metrics;
+use crate::models::odoo_project::Pool;
+
+/// What the runs of a module have done, for `/status`.
+#[derive(Serialize, Default, Clone)]
+pub struct ModuleStatus {
+	pub last_run: Option<NaiveDateTime>,
+	pub last_success: Option<NaiveDateTime>,
+	/// Error of the last run, cleared by a successful one.
+	pub last_error: Option<String>,
+	/// Records handled by the last successful run.
+	pub processed: usize,
+}
+
+#[derive(Serialize, Default, Clone)]
+pub struct Status {
+	/// Whether the last Hula login succeeded, `None` before the first one.
+	pub hula_login: Option<bool>,
+	pub modules: BTreeMap<String, ModuleStatus>,
+}
+
+/// Status shared between `start_background`, which updates it, and the
+/// HTTP endpoints.
+#[derive(Clone, Default)]
+pub struct Health {
+	status: Arc<Mutex<Status>>,
+}
+
+impl Health {
+	/// A poisoned lock only means an update panicked halfway; the status
+	/// is still worth reporting.
+	fn lock(&self) -> MutexGuard<'_, Status> {
+		match self.status.lock() {
+			Ok(guard) => guard,
+			Err(poisoned) => poisoned.into_inner(),
+		}
+	}
+
+	pub fn hula_login(&self, ok: bool) {
+		self.lock().hula_login = Some(ok);
+	}
+
+	/// Records a finished run of `module` that started at `started`.
+	pub fn module_run(&self, module: &str, started: NaiveDateTime, result: &Result<usize, String>) {
+		let mut status = self.lock();
+		let item = status.modules.entry(module.to_string()).or_default();
+
+		item.last_run = Some(started);
+		match result {
+			Ok(processed) => {
+				item.last_success = Some(started);
+				item.last_error = None;
+				item.processed = *processed;
+			}
+			Err(e) => item.last_error = Some(e.clone()),
+		}
+	}
+
+	pub fn status(&self) -> Status {
+		self.lock().clone()
+	}
+}
+
+#[derive(Serialize)]
+struct Readiness {
+	ready: bool,
+	/// Error reaching the database, if any.
+	database: Option<String>,
+	hula_login: Option<bool>,
+}
+
+/// Serves `/healthz`, `/readyz`, `/status` and `/metrics` on `HEALTH_PORT`
+/// (default 8080) until the process exits. A port that cannot be bound is logged
+/// and syncing goes on without the endpoints.
+pub async fn serve(pool: Pool, health: Health) {
+	let port: u16 = std::env::var("HEALTH_PORT")
+		.ok()
+		.and_then(|v| v.parse().ok())
+		.unwrap_or(8080);
+	let addr = SocketAddr::from(([0, 0, 0, 0], port));
+
+	let make_service = make_service_fn(move |_| {
+		let pool = pool.clone();
+		let health = health.clone();
+		async move {
+			Ok::<_, Infallible>(service_fn(move |req| {
+				handle(req, pool.clone(), health.clone())
+			}))
+		}
+	});
+
+	let server = match Server::try_bind(&addr) {
+		Ok(builder) => builder.serve(make_service),
+		Err(e) => {
+			error!("Health endpoints could not listen on {}: {}", addr, e);
+			return;
+		}
+	};
+
+	info!("Health endpoints listening on {}", addr);
+
+	if let Err(e) = server.await {
+		error!("Health endpoints failed: {}", e);
+	}
+}
+
+async fn handle(
+	req: Request<Body>,
+	pool: Pool,
+	health: Health,
+) -> Result<Response<Body>, Infallible> {
+	let response = match (req.method(), req.uri().path()) {
+		(&Method::GET, "/healthz") => text(StatusCode::OK, "ok"),
+		(&Method::GET, "/readyz") => ready(&pool, &health).await,
+		(&Method::GET, "/status") => json(StatusCode::OK, &health.status()),
+		(&Method::GET, "/metrics") => match metrics::render() {
+			Ok(body) => {
+				let mut response = Response::new(Body::from(body));
+				response.headers_mut().insert(
+					hyper::header::CONTENT_TYPE,
+					hyper::header::HeaderValue::from_static("text/plain; version=0.0.4"),
+				);
+				response
+			}
+			Err(e) => {
+				error!("{}", e);
+				text(StatusCode::INTERNAL_SERVER_ERROR, "metrics failed")
+			}
+		},
+		_ => text(StatusCode::NOT_FOUND, "not found"),
+	};
+
+	Ok(response)
+}
+
+/// Ready once the database answers and the last Hula login succeeded.
+async fn ready(pool: &Pool, health: &Health) -> Response<Body> {
+	let checked = db::run(pool, |conn| diesel::sql_query("SELECT 1").execute(conn)).await;
+
+	let database = match checked {
+		Ok(Ok(_)) => None,
+		Ok(Err(e)) => Some(e.to_string()),
+		Err(e) => Some(e),
+	};
+
+	let hula_login = health.status().hula_login;
+	let readiness = Readiness {
+		ready: database.is_none() && hula_login == Some(true),
+		database,
+		hula_login,
+	};
+
+	let status = if readiness.ready {
+		StatusCode::OK
+	} else {
+		StatusCode::SERVICE_UNAVAILABLE
+	};
+
+	json(status, &readiness)
+}
+
+fn text(status: StatusCode, body: &'static str) -> Response<Body> {
+	let mut response = Response::new(Body::from(body));
+	*response.status_mut() = status;
+	response
+}
+
+fn json<T: Serialize>(status: StatusCode, data: &T) -> Response<Body> {
+	let body = match serde_json::to_string(data) {
+		Ok(v) => v,
+		Err(e) => {
+			error!("Serde failed: {}", e);
+			return text(StatusCode::INTERNAL_SERVER_ERROR, "serialization failed");
+		}
+	};
+
+	let mut response = Response::new(Body::from(body));
+	*response.status_mut() = status;
+	response.headers_mut().insert(
+		hyper::header::CONTENT_TYPE,
+		hyper::header::HeaderValue::from_static("application/json"),
+	);
+	response
+}
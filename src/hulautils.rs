@@ -1,4 +1,11 @@
+use log::warn;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::error::{self, SyncError};
+use crate::metrics;
 
 /* use crate::errors::ServiceError; */
 /*use crate::models::test::{Pool, TestData};*/
@@ -8,6 +15,24 @@ pub struct HulaProject {
 	pub id: String,
 	pub description: Option<String>,
 	pub name: String,
+	#[serde(default)]
+	pub external_id: Option<String>,
+	#[serde(default)]
+	pub updated_at: Option<String>,
+}
+
+impl HulaProject {
+	pub fn updated_at(&self) -> Option<chrono::NaiveDateTime> {
+		self.updated_at.as_deref().and_then(parse_timestamp)
+	}
+}
+
+/// Parses a Hula timestamp, with or without UTC offset, as naive UTC.
+pub fn parse_timestamp(value: &str) -> Option<chrono::NaiveDateTime> {
+	match chrono::DateTime::parse_from_rfc3339(value) {
+		Ok(v) => Some(v.naive_utc()),
+		Err(_) => value.parse().ok(),
+	}
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -15,6 +40,8 @@ pub struct HulaApiProject {
 	pub name: String,
 	pub description: Option<String>,
 	pub is_hidden: bool,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub external_id: Option<String>,
 }
 
 /*
@@ -30,25 +57,19 @@ impl From<Error> for SyncError {
 	}
 }*/
 
-pub async fn get_hula_projects(config: &HulaConfig) -> Result<Vec<HulaProject>, &'static str> {
+pub async fn get_hula_projects(config: &HulaConfig) -> Result<Vec<HulaProject>, SyncError> {
 	let request_url = format!("{}/api/projects", config.hula_url);
 	println!("Calling {}", request_url);
 
 	let client = reqwest::Client::new();
 
-	let response = client
-		.get(&request_url)
-		.header("Cookie", format!("auth={}", &config.cookie))
-		.send()
-		.await;
-
-	let response = match response {
-		Ok(file) => file,
-		Err(e) => {
-			println!("{:?}", e);
-			return Err("1");
-		}
-	};
+	let response = send(
+		config,
+		client
+			.get(&request_url)
+			.header("Cookie", format!("auth={}", &config.cookie)),
+	)
+	.await?;
 
 	println!("...Response is: {:?}", &response);
 
@@ -56,17 +77,7 @@ pub async fn get_hula_projects(config: &HulaConfig) -> Result<Vec<HulaProject>,
 		return Ok(Vec::<HulaProject>::new());
 	}
 
-	let jiison = response.json().await;
-
-	let jiison2 = match jiison {
-		Ok(file) => file,
-		Err(e) => {
-			println!("{:?}", e);
-			return Err("2");
-		}
-	};
-
-	let projects: Vec<HulaProject> = jiison2;
+	let projects: Vec<HulaProject> = decode(response).await?;
 
 	println!("...Got {} projects.", projects.len());
 
@@ -77,46 +88,32 @@ pub async fn insert_hula_project(
 	config: &HulaConfig,
 	name: String,
 	description: Option<String>,
-) -> Result<String, &'static str> {
+	external_id: String,
+) -> Result<String, SyncError> {
 	let request_url = format!("{}/api/projects", config.hula_url);
 	println!("Calling {}", request_url);
 
 	let project = HulaApiProject {
-		name: name,
-		description: description,
+		name,
+		description,
 		is_hidden: false,
+		external_id: Some(external_id),
 	};
 
 	let client = reqwest::Client::new();
 
-	let response = client
-		.post(&request_url)
-		.header("Cookie", format!("auth={}", config.cookie))
-		.json(&project)
-		.send()
-		.await;
-
-	let response = match response {
-		Ok(file) => file,
-		Err(e) => {
-			println!("{:?}", e);
-			return Err("1");
-		}
-	};
+	let response = send(
+		config,
+		client
+			.post(&request_url)
+			.header("Cookie", format!("auth={}", config.cookie))
+			.json(&project),
+	)
+	.await?;
 
 	println!("...Response is: {:?}", &response);
 
-	let jiison = response.json().await;
-
-	let jiison2 = match jiison {
-		Ok(file) => file,
-		Err(e) => {
-			println!("{:?}", e);
-			return Err("2");
-		}
-	};
-
-	let project: HulaProject = jiison2;
+	let project: HulaProject = decode(response).await?;
 
 	Ok(project.id)
 }
@@ -126,41 +123,192 @@ pub async fn update_hula_project(
 	project_id: String,
 	name: String,
 	description: Option<String>,
-) -> Result<(), &'static str> {
+) -> Result<(), SyncError> {
 	let request_url = format!("{}/api/projects/{}", config.hula_url, project_id.clone());
 	println!("Calling {}", request_url);
 
 	let project = HulaApiProject {
-		description: description,
+		description,
 		is_hidden: false,
-		name: name,
+		name,
+		external_id: None,
 	};
 
 	let client = reqwest::Client::new();
 
-	let response = client
-		.put(&request_url)
-		.header("Cookie", format!("auth={}", config.cookie))
-		.json(&project)
-		.send()
-		.await;
-
-	let response = match response {
-		Ok(file) => file,
-		Err(e) => {
-			println!("{:?}", e);
-			return Err("1");
-		}
-	};
+	let response = send(
+		config,
+		client
+			.put(&request_url)
+			.header("Cookie", format!("auth={}", config.cookie))
+			.json(&project),
+	)
+	.await?;
 
 	println!("...Response is: {:?}", &response);
 
+	if !response.status().is_success() {
+		return Err(SyncError::Status(response.status()));
+	}
+
 	Ok(())
 }
 
+/// The JSON body of a successful response.
+async fn decode<T: DeserializeOwned>(response: Response) -> Result<T, SyncError> {
+	if !response.status().is_success() {
+		return Err(SyncError::Status(response.status()));
+	}
+
+	response.json().await.map_err(SyncError::Decode)
+}
+
+/// Finds the Hula project created for a mapping row, if any.
+pub fn find_by_external_id<'a>(
+	projects: &'a [HulaProject],
+	external_id: &uuid::Uuid,
+) -> Option<&'a HulaProject> {
+	let external_id = external_id.to_string();
+	projects
+		.iter()
+		.find(|x| x.external_id.as_deref() == Some(&external_id[..]))
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Skill {
+	pub id: uuid::Uuid,
+	pub label: String,
+	pub aliases: Vec<String>,
+}
+
+/// A level a need's skill can require, e.g. "Senior".
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SkillScopeLevel {
+	pub id: uuid::Uuid,
+	pub label: String,
+}
+
+/// Hula's skill catalogue, which the CRM modules mirror.
+pub async fn get_hula_skills(config: &HulaConfig) -> Result<Vec<Skill>, SyncError> {
+	get_catalogue(config, "/api/skills").await
+}
+
+/// Hula's skill scope levels, which the CRM modules mirror.
+pub async fn get_hula_skill_levels(config: &HulaConfig) -> Result<Vec<SkillScopeLevel>, SyncError> {
+	get_catalogue(config, "/api/skillscopelevels").await
+}
+
+async fn get_catalogue<T: DeserializeOwned>(
+	config: &HulaConfig,
+	path: &str,
+) -> Result<Vec<T>, SyncError> {
+	let request_url = format!("{}{}", config.hula_url, path);
+	let client = reqwest::Client::new();
+	let response = send(
+		config,
+		client
+			.get(&request_url)
+			.header("Cookie", format!("auth={}", config.cookie)),
+	)
+	.await?;
+
+	if response.status() == StatusCode::NO_CONTENT {
+		return Ok(Vec::new());
+	}
+
+	decode(response).await
+}
+
 pub struct HulaConfig {
 	pub hula_url: String,
 	pub cookie: String,
+	/// How many requests the modules may have in flight at once.
+	pub concurrency: usize,
+	/// How many times a request is retried after 429 Too Many Requests.
+	pub max_retries: u32,
+	/// Longest wait before such a retry. A longer Retry-After is not honoured.
+	pub max_retry_wait: Duration,
+	/// Base URL of the Hula UI as users reach it. May differ from `hula_url`.
+	pub public_url: String,
+	/// Path of a project page below `public_url`. `{id}` is replaced with the
+	/// project id.
+	pub link_path: String,
+}
+
+/// Link to a project in the Hula UI, for writing back to the CRM.
+pub fn project_link(config: &HulaConfig, id: &uuid::Uuid) -> String {
+	format!(
+		"{}{}",
+		config.public_url.trim_end_matches('/'),
+		config.link_path.replace("{id}", &id.to_string())
+	)
+}
+
+/// Sends a request to Hula. While Hula answers 429 the request is retried
+/// after the delay given in Retry-After, or with exponential backoff when
+/// the header is missing or asks for more than `HULA_MAX_RETRY_WAIT`
+/// seconds (default 60).
+pub async fn send(config: &HulaConfig, request: RequestBuilder) -> reqwest::Result<Response> {
+	let mut request = request;
+	let mut attempt: u32 = 0;
+
+	loop {
+		let next = request.try_clone();
+		let started = Instant::now();
+		let response = request.send().await;
+		metrics::request("hula", started, &response);
+		let response = response?;
+
+		if response.status() != StatusCode::TOO_MANY_REQUESTS || attempt >= config.max_retries {
+			return Ok(response);
+		}
+
+		let next = match next {
+			Some(n) => n,
+			None => return Ok(response),
+		};
+
+		let wait = retry_wait(retry_after(&response), attempt, config.max_retry_wait);
+		warn!(
+			"Hula rate limit hit on {}, retrying in {} seconds.",
+			response.url(),
+			wait.as_secs()
+		);
+
+		tokio::time::sleep(wait).await;
+		attempt += 1;
+		request = next;
+	}
+}
+
+fn retry_after(response: &Response) -> Option<Duration> {
+	let value = response
+		.headers()
+		.get(reqwest::header::RETRY_AFTER)?
+		.to_str()
+		.ok()?;
+
+	parse_retry_after(value, chrono::Utc::now())
+}
+
+/// A `Retry-After` value, either seconds or an HTTP date, as a wait from
+/// `now`. A date in the past gives `None`.
+fn parse_retry_after(value: &str, now: chrono::DateTime<chrono::Utc>) -> Option<Duration> {
+	if let Ok(seconds) = value.trim().parse::<u64>() {
+		return Some(Duration::from_secs(seconds));
+	}
+
+	let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+	at.signed_duration_since(now).to_std().ok()
+}
+
+/// The wait before retry `attempt`: Retry-After if Hula sent one within
+/// `max`, otherwise exponential backoff capped at `max`.
+fn retry_wait(retry_after: Option<Duration>, attempt: u32, max: Duration) -> Duration {
+	match retry_after {
+		Some(wait) if wait <= max => wait,
+		_ => Duration::from_secs(1 << attempt.min(6)).min(max),
+	}
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -169,10 +317,10 @@ pub struct AuthData {
 	pub password: String,
 }
 
-pub async fn get_config() -> Result<HulaConfig, &'static str> {
-	let hula_url = std::env::var("HULA_URL").expect("HULA_URL must be set");
-	let hula_uid = std::env::var("HULA_USER_ID").expect("HULA_USER_ID must be set");
-	let hula_pwd = std::env::var("HULA_USER_PWD").expect("HULA_USER_PWD must be set");
+pub async fn get_config() -> Result<HulaConfig, SyncError> {
+	let hula_url = error::env("HULA_URL")?;
+	let hula_uid = error::env("HULA_USER_ID")?;
+	let hula_pwd = error::env("HULA_USER_PWD")?;
 
 	let request_url = format!("{}/api/auth", hula_url);
 	println!("Calling {}", request_url);
@@ -184,13 +332,15 @@ pub async fn get_config() -> Result<HulaConfig, &'static str> {
 
 	let client = reqwest::Client::new();
 
+	let started = Instant::now();
 	let response = client.post(&request_url).json(&data).send().await;
+	metrics::request("hula", started, &response);
 
 	let response = match response {
 		Ok(file) => file,
 		Err(e) => {
 			println!("{:?}", e);
-			return Err("1");
+			return Err(e.into());
 		}
 	};
 
@@ -198,37 +348,115 @@ pub async fn get_config() -> Result<HulaConfig, &'static str> {
 	let cookie = match cookie {
 		Some(c) => c,
 		None => {
-			return Err("11");
+			return Err(SyncError::Other(format!(
+				"Hula login returned no cookie, status {}",
+				response.status()
+			)));
 		}
 	};
 
 	let config = HulaConfig {
-		hula_url: std::env::var("HULA_URL").expect("HULA_URL must be set"),
+		public_url: std::env::var("HULA_PUBLIC_URL").unwrap_or_else(|_| hula_url.clone()),
+		link_path: std::env::var("HULA_LINK_PATH")
+			.unwrap_or_else(|_| "/app/project/{id}".to_string()),
+		hula_url,
 		cookie: cookie.value().to_string(),
+		concurrency: std::env::var("HULA_CONCURRENCY")
+			.ok()
+			.and_then(|v| v.parse().ok())
+			.filter(|v| *v > 0)
+			.unwrap_or(4),
+		max_retries: std::env::var("HULA_MAX_RETRIES")
+			.ok()
+			.and_then(|v| v.parse().ok())
+			.unwrap_or(5),
+		max_retry_wait: Duration::from_secs(
+			std::env::var("HULA_MAX_RETRY_WAIT")
+				.ok()
+				.and_then(|v| v.parse().ok())
+				.unwrap_or(60),
+		),
 	};
 
 	Ok(config)
 }
 
-pub async fn close_config(config: &HulaConfig) -> Result<(), &'static str> {
+pub async fn close_config(config: &HulaConfig) -> Result<(), SyncError> {
 	let request_url = format!("{}/api/auth", config.hula_url);
 	println!("Calling {}", request_url);
 
 	let client = reqwest::Client::new();
 
+	let started = Instant::now();
 	let response = client
 		.delete(&request_url)
 		.header("Cookie", format!("auth={}", config.cookie))
 		.send()
 		.await;
+	metrics::request("hula", started, &response);
 
-	let _ = match response {
-		Ok(file) => file,
-		Err(e) => {
-			println!("{:?}", e);
-			return Err("1");
-		}
-	};
+	let response = response?;
+
+	if !response.status().is_success() {
+		return Err(SyncError::Status(response.status()));
+	}
 
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono::TimeZone;
+
+	fn now() -> chrono::DateTime<chrono::Utc> {
+		chrono::Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap()
+	}
+
+	#[test]
+	fn retry_after_seconds() {
+		assert_eq!(
+			parse_retry_after("120", now()),
+			Some(Duration::from_secs(120))
+		);
+	}
+
+	#[test]
+	fn retry_after_http_date() {
+		assert_eq!(
+			parse_retry_after("Fri, 01 Mar 2024 12:00:30 GMT", now()),
+			Some(Duration::from_secs(30))
+		);
+		assert_eq!(
+			parse_retry_after("Fri, 01 Mar 2024 11:59:00 GMT", now()),
+			None
+		);
+	}
+
+	#[test]
+	fn retry_after_invalid() {
+		assert_eq!(parse_retry_after("soon", now()), None);
+	}
+
+	#[test]
+	fn retry_wait_honours_retry_after_up_to_the_maximum() {
+		let max = Duration::from_secs(60);
+
+		assert_eq!(
+			retry_wait(Some(Duration::from_secs(30)), 0, max),
+			Duration::from_secs(30)
+		);
+		assert_eq!(
+			retry_wait(Some(Duration::from_secs(86400)), 2, max),
+			Duration::from_secs(4)
+		);
+	}
+
+	#[test]
+	fn retry_wait_backoff_is_capped() {
+		let max = Duration::from_secs(10);
+
+		assert_eq!(retry_wait(None, 1, max), Duration::from_secs(2));
+		assert_eq!(retry_wait(None, 10, max), max);
+	}
+}
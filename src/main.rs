@@ -5,15 +5,26 @@ use diesel::prelude::*;
 use diesel::r2d2::{self, ConnectionManager};
 
 mod background;
+mod changeset;
+mod conflicts;
+mod db;
+mod dead_letter;
+mod error;
+mod health;
 mod hulautils;
+mod metrics;
 mod models;
 mod modules;
+mod orphans;
+mod reconcile;
+mod retries;
 mod schema;
+mod shutdown;
 
 fn initialize_db(name: &str) {
 	println!("Running database migrations...");
 	let connection =
-		PgConnection::establish(&name).expect(&format!("Error connecting to {}", name));
+		PgConnection::establish(name).unwrap_or_else(|_| panic!("Error connecting to {}", name));
 
 	let result = diesel_migrations::run_pending_migrations(&connection);
 
@@ -43,5 +54,26 @@ async fn main() -> std::io::Result<()> {
 		.build(manager)
 		.expect("Failed to create pool.");
 
-	Ok(background::start_background(pool.clone()).await)
+	let args: Vec<String> = std::env::args().collect();
+
+	match args.get(1).map(|x| &x[..]) {
+		Some("reconcile") => match reconcile::run(pool, &args[2..]).await {
+			Ok(_) => Ok(()),
+			Err(e) => Err(std::io::Error::other(e)),
+		},
+		Some("conflicts") => match conflicts::run(pool, &args[2..]).await {
+			Ok(_) => Ok(()),
+			Err(e) => Err(std::io::Error::other(e)),
+		},
+		Some("deadletter") => match dead_letter::run(pool, &args[2..]).await {
+			Ok(_) => Ok(()),
+			Err(e) => Err(std::io::Error::other(e)),
+		},
+		_ => {
+			let health = health::Health::default();
+			tokio::spawn(health::serve(pool.clone(), health.clone()));
+			background::start_background(pool, health).await;
+			Ok(())
+		}
+	}
 }
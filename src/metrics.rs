@@ -0,0 +1,195 @@
+use log::error;
+use prometheus::core::Collector;
+use prometheus::{
+	Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+/// Prometheus metrics of the sync runs, served on `/metrics`.
+struct Metrics {
+	registry: Registry,
+	cycles: IntCounterVec,
+	last_success: IntGaugeVec,
+	records: IntCounterVec,
+	request_duration: HistogramVec,
+	responses: IntCounterVec,
+	script_duration: HistogramVec,
+	logins: IntCounterVec,
+}
+
+impl Metrics {
+	fn new() -> Metrics {
+		let registry = Registry::new_custom(Some("hulasync".to_string()), None)
+			.expect("metric prefix is valid");
+
+		Metrics {
+			cycles: register(
+				&registry,
+				IntCounterVec::new(
+					Opts::new("cycles_total", "Sync runs per module and outcome"),
+					&["module", "outcome"],
+				),
+			),
+			last_success: register(
+				&registry,
+				IntGaugeVec::new(
+					Opts::new(
+						"last_success_timestamp_seconds",
+						"Unix time of the last successful run per module",
+					),
+					&["module"],
+				),
+			),
+			records: register(
+				&registry,
+				IntCounterVec::new(
+					Opts::new(
+						"records_total",
+						"CRM records fetched, created, updated, skipped or failed",
+					),
+					&["module", "action"],
+				),
+			),
+			request_duration: register(
+				&registry,
+				HistogramVec::new(
+					HistogramOpts::new(
+						"request_duration_seconds",
+						"Latency of Hula and HubSpot requests",
+					),
+					&["service"],
+				),
+			),
+			responses: register(
+				&registry,
+				IntCounterVec::new(
+					Opts::new(
+						"responses_total",
+						"Hula and HubSpot responses per status code, `error` if none",
+					),
+					&["service", "status"],
+				),
+			),
+			script_duration: register(
+				&registry,
+				HistogramVec::new(
+					HistogramOpts::new("odoo_script_duration_seconds", "Run time of Odoo scripts")
+						.buckets(vec![0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0]),
+					&["script", "outcome"],
+				),
+			),
+			logins: register(
+				&registry,
+				IntCounterVec::new(
+					Opts::new("hula_logins_total", "Hula logins per outcome"),
+					&["outcome"],
+				),
+			),
+			registry,
+		}
+	}
+}
+
+/// The metric definitions are constant, so creating one only fails on a
+/// typo in its name. A failed registration leaves the metric unexported.
+fn register<T: Collector + Clone + 'static>(
+	registry: &Registry,
+	metric: prometheus::Result<T>,
+) -> T {
+	let metric = metric.expect("metric definition is valid");
+
+	if let Err(e) = registry.register(Box::new(metric.clone())) {
+		error!("Registering metric failed: {}", e);
+	}
+
+	metric
+}
+
+fn metrics() -> &'static Metrics {
+	static METRICS: OnceLock<Metrics> = OnceLock::new();
+	METRICS.get_or_init(Metrics::new)
+}
+
+fn outcome(ok: bool) -> &'static str {
+	if ok {
+		"ok"
+	} else {
+		"error"
+	}
+}
+
+/// Counts a finished run of `module`.
+pub fn module_run(module: &str, result: &Result<usize, String>) {
+	let m = metrics();
+
+	m.cycles
+		.with_label_values(&[module, outcome(result.is_ok())])
+		.inc();
+
+	if result.is_ok() {
+		m.last_success
+			.with_label_values(&[module])
+			.set(chrono::Utc::now().timestamp());
+	}
+}
+
+/// Counts `count` records of `module` that were fetched, created, updated,
+/// skipped or failed.
+pub fn records(module: &str, action: &str, count: usize) {
+	metrics()
+		.records
+		.with_label_values(&[module, action])
+		.inc_by(count as u64);
+}
+
+/// Records a request to `service` sent at `started`.
+pub fn request(service: &str, started: Instant, result: &reqwest::Result<reqwest::Response>) {
+	let m = metrics();
+
+	let status = match result {
+		Ok(response) => response.status().as_u16().to_string(),
+		Err(e) => match e.status() {
+			Some(status) => status.as_u16().to_string(),
+			None => "error".to_string(),
+		},
+	};
+
+	m.request_duration
+		.with_label_values(&[service])
+		.observe(started.elapsed().as_secs_f64());
+	m.responses.with_label_values(&[service, &status]).inc();
+}
+
+/// Records a run of the Odoo script at `path` started at `started`.
+pub fn odoo_script(path: &str, started: Instant, ok: bool) {
+	let script = std::path::Path::new(path)
+		.file_name()
+		.and_then(|x| x.to_str())
+		.unwrap_or(path);
+
+	metrics()
+		.script_duration
+		.with_label_values(&[script, outcome(ok)])
+		.observe(started.elapsed().as_secs_f64());
+}
+
+/// Counts a Hula login. The service logs in again on every cycle.
+pub fn hula_login(ok: bool) {
+	metrics().logins.with_label_values(&[outcome(ok)]).inc();
+}
+
+/// All metrics in the Prometheus text format.
+pub fn render() -> Result<String, String> {
+	let mut buffer = vec![];
+
+	let encoded = TextEncoder::new().encode(&metrics().registry.gather(), &mut buffer);
+	if let Err(e) = encoded {
+		return Err(format!("Encoding metrics failed: {}", e));
+	}
+
+	match String::from_utf8(buffer) {
+		Ok(text) => Ok(text),
+		Err(e) => Err(format!("Encoding metrics failed: {}", e)),
+	}
+}
@@ -2,3 +2,7 @@ pub mod hubspot_project;
 pub mod hula_call_log;
 pub mod odoo_call_log;
 pub mod odoo_project;
+pub mod sync_conflict;
+pub mod sync_dead_letter;
+pub mod sync_retry;
+pub mod sync_status;
@@ -6,8 +6,9 @@ use serde::{Deserialize, Serialize};
 #[table_name = "hubspot_projects"]
 pub struct HubspotProject {
 	pub id: uuid::Uuid,
-	pub hula_id: uuid::Uuid,
+	pub hula_id: Option<uuid::Uuid>,
 	pub hubspot_id: String,
 	pub name: String,
 	pub updated_by: String,
+	pub sync_status: String,
 }
@@ -9,8 +9,13 @@ pub type Pool = r2d2::Pool<ConnectionManager<PgConnection>>;
 #[table_name = "odoo_projects"]
 pub struct OdooProject {
 	pub id: uuid::Uuid,
-	pub hula_id: uuid::Uuid,
+	pub hula_id: Option<uuid::Uuid>,
 	pub odoo_id: i32,
 	pub name: String,
 	pub updated_by: String,
+	pub sync_status: String,
+	pub content_hash: Option<String>,
+	pub summary_hash: Option<String>,
+	pub synced_at: Option<chrono::NaiveDateTime>,
+	pub snapshot: Option<String>,
 }
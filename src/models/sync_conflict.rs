@@ -0,0 +1,27 @@
+use super::super::schema::*;
+use serde::{Deserialize, Serialize};
+
+/// Conflict waiting for someone to pick a side.
+pub const OPEN: &str = "open";
+/// Resolved in favour of the CRM value, applied on the next sync run.
+pub const KEEP_CRM: &str = "crm";
+/// Resolved in favour of the Hula value, applied on the next sync run.
+pub const KEEP_HULA: &str = "hula";
+/// Resolution has been written to both sides.
+pub const APPLIED: &str = "applied";
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Insertable)]
+#[table_name = "sync_conflicts"]
+pub struct SyncConflict {
+	pub id: uuid::Uuid,
+	pub source: String,
+	pub mapping_id: uuid::Uuid,
+	pub crm_id: String,
+	pub hula_id: uuid::Uuid,
+	pub field: String,
+	pub crm_value: String,
+	pub hula_value: String,
+	pub status: String,
+	pub updated_by: String,
+	pub updated_at: chrono::NaiveDateTime,
+}
@@ -0,0 +1,25 @@
+use super::super::schema::*;
+use serde::{Deserialize, Serialize};
+
+/// Failed too often, no longer retried automatically.
+pub const DEAD: &str = "dead";
+/// Queued for another attempt from the CLI, made on the next sync run with
+/// the record as it is in the CRM.
+pub const RETRY: &str = "retry";
+/// Like `RETRY`, but the edited `payload` is sent instead of the record.
+pub const EDITED: &str = "edited";
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Insertable)]
+#[table_name = "sync_dead_letter"]
+pub struct SyncDeadLetter {
+	pub id: uuid::Uuid,
+	pub source: String,
+	pub crm_id: String,
+	/// The structure last sent to Hula, as JSON.
+	pub payload: String,
+	pub error: String,
+	pub attempts: i32,
+	pub status: String,
+	pub updated_by: String,
+	pub updated_at: chrono::NaiveDateTime,
+}
@@ -0,0 +1,22 @@
+use super::super::schema::*;
+use serde::{Deserialize, Serialize};
+
+/// A CRM record whose sync failed, tried again once `next_attempt_at` has
+/// passed.
+#[derive(Debug, Serialize, Deserialize, Queryable, Insertable)]
+#[table_name = "sync_retries"]
+pub struct SyncRetry {
+	pub id: uuid::Uuid,
+	pub source: String,
+	pub crm_id: String,
+	pub attempts: i32,
+	pub next_attempt_at: chrono::NaiveDateTime,
+	pub last_error: String,
+	pub updated_by: String,
+	pub updated_at: chrono::NaiveDateTime,
+	/// What was sent to Hula, to tell whether the record changed since.
+	pub payload: Option<String>,
+	/// Failed attempts the target rejected, as opposed to could not be
+	/// reached. Only these count towards the dead-letter queue.
+	pub rejections: i32,
+}
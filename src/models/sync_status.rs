@@ -0,0 +1,12 @@
+/// Mapping row reserved before the Hula project was created. Its `id` is sent
+/// to Hula as the project's external id.
+pub const PENDING: &str = "pending";
+/// Mapping row whose Hula project is known to exist.
+pub const SYNCED: &str = "synced";
+/// Mapping row whose Hula project was deleted on purpose. The CRM record is
+/// no longer synced.
+pub const REMOVED: &str = "removed";
+/// Mapping row whose CRM record was missing when `reconcile --fix
+/// stale_mapping` ran. It is set back to synced once the record is returned
+/// again.
+pub const STALE: &str = "stale";
@@ -1 +1,2 @@
 pub mod hubspot_module;
+pub mod skill_property;
@@ -1,16 +1,36 @@
-use diesel::{prelude::*, PgConnection};
-use serde::{Deserialize, Serialize};
-
+use diesel::prelude::*;
+use futures::stream::{self, StreamExt};
+use log::{error, trace, warn};
+use serde::Deserialize;
+
+use crate::changeset::{self, Create, CrmRecord, Mapped, Mapping, ProjectIndex};
+use crate::db;
+use crate::error::{self, SyncError};
 use crate::hulautils::HulaConfig;
-use crate::hulautils::{get_hula_projects, insert_hula_project, update_hula_project, HulaProject};
+use crate::hulautils::{
+	find_by_external_id, get_hula_projects, insert_hula_project, update_hula_project, HulaProject,
+};
+use crate::metrics;
 use crate::models::hubspot_project::HubspotProject;
+use crate::models::odoo_project::Pool;
+use crate::models::sync_status;
+use crate::orphans::{self, OrphanPolicy};
+use crate::reconcile::{self, Category, Issue, SourceReport};
+
+use super::skill_property::{self, SkillPropertyConfig};
 
+use std::collections::{HashMap, HashSet};
 use std::str;
+use std::time::Instant;
 use uuid::Uuid;
 
 #[derive(Deserialize, Debug)]
 pub struct HubspotHeader {
 	deals: Vec<HubspotDeal>,
+	#[serde(default, rename = "hasMore")]
+	has_more: bool,
+	#[serde(default)]
+	offset: u64,
 }
 
 #[allow(non_snake_case)]
@@ -42,202 +62,628 @@ pub struct HubspotPalvelut {
 	value: String,
 }
 
-#[derive(Serialize, Debug)]
-pub struct HubspotLimit {
-	after: u64,
-}
+/// Runs one HubSpot sync. Returns the number of deals created or updated.
+pub async fn do_process(config: &HulaConfig, pool: &Pool) -> Result<usize, SyncError> {
+	trace!("Processing HubSpot interface.");
 
-pub async fn do_process(config: &HulaConfig, conn: &PgConnection) -> Result<(), String> {
-	println!("Henlo world");
+	if let Some(property) = SkillPropertyConfig::from_env() {
+		skill_property::sync_skills_to_hubspot(config, &property).await?;
+	}
 
-	let hubspot_deals = get_hubspot_deals().await;
-	println!("hubspot gotten");
+	let hubspot_deals = get_hubspot_deals().await?;
+	trace!("Got HubSpot deals: {}", hubspot_deals.len());
 
-	let hula_projects = get_hula_projects(&config).await;
-	println!("hula gotten");
+	let hula_projects = get_hula_projects(config).await?;
+	trace!("Got Hula project descriptions: {}", hula_projects.len());
 
-	let log = get_hubspot_log(&conn);
-	println!("logs gotten: {:?}", log);
+	let log = get_hubspot_log(pool).await?;
+	trace!("Got HubSpot project mappings: {}", log.len());
 
-	let _ = do_process2(
-		&config,
-		&conn,
-		hubspot_deals.unwrap().deals,
-		hula_projects.unwrap(),
-		log.unwrap(),
-	)
-	.await;
-	println!("ready");
+	let log = restore_stale_hubspot_log(pool, &hubspot_deals, log).await?;
+	let log = reconcile_pending_hubspot_log(pool, &hula_projects, log).await?;
+	let (log, _) =
+		handle_orphaned_hubspot_log(pool, &hula_projects, log, OrphanPolicy::from_env()).await?;
 
-	Ok(())
+	let processed = do_process2(config, pool, hubspot_deals, hula_projects, log).await?;
+	trace!("Processed HubSpot deals: {}", processed);
+
+	Ok(processed)
 }
 
-pub async fn get_hubspot_deals() -> Result<HubspotHeader, &'static str> {
-	let hubspot_key = std::env::var("HUBSPOT_API_KEY").expect("HUBSPOT_API_KEY must be set");
+/// Compares HubSpot deals, `hubspot_projects` and Hula projects for the
+/// `reconcile` command, fixing the categories listed in `fix`.
+pub async fn reconcile(
+	config: &HulaConfig,
+	pool: &Pool,
+	projects: &[HulaProject],
+	fix: &[Category],
+) -> Result<SourceReport, String> {
+	let all_deals = fetch_hubspot_deals().await?;
+	let existing: HashSet<String> = all_deals.iter().map(|x| x.dealId.to_string()).collect();
+	let deals: Vec<HubspotDeal> = all_deals.into_iter().filter(in_synced_stage).collect();
+	let log = get_hubspot_log(pool).await?;
+
+	let mut issues = vec![];
+
+	let pending: Vec<uuid::Uuid> = log
+		.iter()
+		.filter(|x| x.sync_status == sync_status::PENDING)
+		.map(|x| x.id)
+		.collect();
+
+	let log = if fix.contains(&Category::PendingMapping) {
+		reconcile_pending_hubspot_log(pool, projects, log).await?
+	} else {
+		log
+	};
 
-	let request_url = format!("https://api.hubapi.com/deals/v1/deal/paged?hapikey={}&properties=dealname&properties=dealstage&properties=palvelut&limit=250",
-		hubspot_key);
+	for row in log.iter().filter(|x| pending.contains(&x.id)) {
+		let fixed = row.sync_status == sync_status::SYNCED;
+		issues.push(Issue {
+			category: Category::PendingMapping,
+			source: "hubspot",
+			crm_id: Some(row.hubspot_id.clone()),
+			hula_id: row.hula_id.map(|x| x.to_string()),
+			detail: if fixed {
+				"Project found by external id".to_string()
+			} else {
+				"No Hula project with this external id".to_string()
+			},
+			fixed,
+		});
+	}
 
-	println!("Calling {}", request_url);
+	let policy = OrphanPolicy::from_env();
+	let fix_orphans = fix.contains(&Category::OrphanedMapping) && policy != OrphanPolicy::Ignore;
+	let fix_stale = fix.contains(&Category::StaleMapping);
+	let fix_drift = fix.contains(&Category::NameDrift);
+	let mut orphan_issues = vec![];
+
+	issues.extend(reconcile::find_duplicate_mappings("hubspot", &log));
+
+	let index = ProjectIndex::new(projects);
+	let by_id: HashMap<String, &HubspotDeal> =
+		deals.iter().map(|x| (x.dealId.to_string(), x)).collect();
+
+	for row in log.iter().filter(|x| x.sync_status == sync_status::SYNCED) {
+		let (hula_id, project) = match row.hula_id {
+			Some(id) => (id.to_string(), index.get(&id)),
+			None => continue,
+		};
+
+		let deal = by_id.get(&row.hubspot_id).copied();
+
+		if project.is_none() {
+			orphan_issues.push(issues.len());
+			issues.push(Issue {
+				category: Category::OrphanedMapping,
+				source: "hubspot",
+				crm_id: Some(row.hubspot_id.clone()),
+				hula_id: Some(hula_id.clone()),
+				detail: format!("Hula project for '{}' no longer exists", &row.name),
+				fixed: false,
+			});
+		}
 
-	let client = reqwest::Client::new();
-	let response = client.get(&request_url).send().await;
+		if !existing.contains(&row.hubspot_id) {
+			let mut fixed = false;
+			if fix_stale {
+				update_hubspot_log(pool, row.id, row.hula_id, sync_status::STALE).await?;
+				fixed = true;
+			}
 
-	let response = match response {
-		Ok(file) => file,
-		Err(e) => {
-			println!("{:?}", e);
-			return Err("1");
+			issues.push(Issue {
+				category: Category::StaleMapping,
+				source: "hubspot",
+				crm_id: Some(row.hubspot_id.clone()),
+				hula_id: Some(hula_id.clone()),
+				detail: format!("Deal '{}' is deleted", &row.name),
+				fixed,
+			});
 		}
-	};
 
-	println!("...Response is: {:?}", &response);
+		if let (Some(project), Some(deal)) = (project, deal) {
+			if project.name != deal.properties.dealname.value {
+				let mut fixed = false;
+				if fix_drift {
+					let palvelut = deal.properties.palvelut.as_ref().map(|x| x.value.clone());
+					update_hula_project(
+						config,
+						project.id.clone(),
+						deal.properties.dealname.value.clone(),
+						palvelut,
+					)
+					.await?;
+					fixed = true;
+				}
 
-	let jiison = response.json().await;
+				issues.push(Issue {
+					category: Category::NameDrift,
+					source: "hubspot",
+					crm_id: Some(row.hubspot_id.clone()),
+					hula_id: Some(hula_id.clone()),
+					detail: format!(
+						"HubSpot: '{}', Hula: '{}'",
+						&deal.properties.dealname.value, &project.name
+					),
+					fixed,
+				});
+			}
+		}
+	}
+
+	let mapped: HashSet<&String> = log.iter().map(|x| &x.hubspot_id).collect();
 
-	let jiison2 = match jiison {
-		Ok(file) => file,
-		Err(e) => {
-			println!("{:?}", e);
-			return Err("2");
+	for deal in &deals {
+		if !mapped.contains(&deal.dealId.to_string()) {
+			issues.push(Issue {
+				category: Category::UnmappedCrm,
+				source: "hubspot",
+				crm_id: Some(deal.dealId.to_string()),
+				hula_id: None,
+				detail: format!(
+					"Deal '{}' has no Hula project",
+					&deal.properties.dealname.value
+				),
+				fixed: false,
+			});
 		}
-	};
+	}
 
-	let mut header: HubspotHeader = jiison2;
+	let mapped_hula_ids = log
+		.iter()
+		.filter_map(|x| x.hula_id)
+		.map(|x| x.to_string())
+		.collect();
 
-	println!("...Got {}", header.deals.len());
+	if fix_orphans {
+		let (_, applied) = handle_orphaned_hubspot_log(pool, projects, log, policy).await?;
+		for idx in orphan_issues {
+			issues[idx].fixed = applied != OrphanPolicy::Ignore;
+		}
+	}
 
-	header
-		.deals
-		.retain(|x| x.properties.dealstage.value == "1479299");
+	Ok(SourceReport {
+		issues,
+		mapped_hula_ids,
+	})
+}
 
-	println!("...Filtered. Remaining with {}", header.deals.len());
+/// Deals in the stage that is synced to Hula.
+pub async fn get_hubspot_deals() -> Result<Vec<HubspotDeal>, SyncError> {
+	let mut deals = fetch_hubspot_deals().await?;
+	deals.retain(in_synced_stage);
 
-	println!(
-		"--------------------------------------------------------------- {:?}",
-		header.deals
-	);
+	trace!("Deals in the synced stage: {}", deals.len());
+
+	Ok(deals)
+}
 
-	Ok(header)
+fn in_synced_stage(deal: &HubspotDeal) -> bool {
+	deal.properties.dealstage.value == "1479299"
 }
 
-fn get_hubspot_log(conn: &PgConnection) -> Result<Vec<HubspotProject>, String> {
+/// All deals, fetched page by page.
+async fn fetch_hubspot_deals() -> Result<Vec<HubspotDeal>, SyncError> {
+	let hubspot_key = error::env("HUBSPOT_API_KEY")?;
+
+	let client = reqwest::Client::new();
+	let mut deals = vec![];
+	let mut offset = None;
+
+	loop {
+		let mut request_url = format!("https://api.hubapi.com/deals/v1/deal/paged?hapikey={}&properties=dealname&properties=dealstage&properties=palvelut&limit=250",
+			hubspot_key);
+		if let Some(offset) = offset {
+			request_url.push_str(&format!("&offset={}", offset));
+		}
+
+		trace!("Calling the HubSpot deals API.");
+
+		let started = Instant::now();
+		let response = client.get(&request_url).send().await;
+		metrics::request("hubspot", started, &response);
+
+		let response = response?;
+
+		if !response.status().is_success() {
+			return Err(SyncError::Status(response.status()));
+		}
+
+		let page: HubspotHeader = response.json().await.map_err(SyncError::Decode)?;
+		deals.extend(page.deals);
+
+		if !page.has_more || offset == Some(page.offset) {
+			break;
+		}
+		offset = Some(page.offset);
+	}
+
+	trace!("Got {} deals from HubSpot.", deals.len());
+
+	Ok(deals)
+}
+
+async fn get_hubspot_log(pool: &Pool) -> Result<Vec<HubspotProject>, SyncError> {
 	use crate::schema::hubspot_projects::dsl::hubspot_projects;
-	let items = hubspot_projects
-		.load::<HubspotProject>(conn)
-		.expect("failed to load from db");
+	let items = db::run(pool, |conn| hubspot_projects.load::<HubspotProject>(conn)).await??;
+
+	Ok(items)
+}
+
+impl CrmRecord for HubspotDeal {
+	type Key = String;
+
+	fn crm_key(&self) -> String {
+		self.dealId.to_string()
+	}
+}
 
-	println!("\nGot all logs.\n");
-	return Ok(items);
+impl Mapping for HubspotProject {
+	type Key = String;
+
+	fn crm_key(&self) -> String {
+		self.hubspot_id.clone()
+	}
+
+	fn hula_id(&self) -> Option<uuid::Uuid> {
+		self.hula_id
+	}
+
+	fn sync_status(&self) -> &str {
+		&self.sync_status
+	}
 }
 
 async fn do_process2(
 	config: &HulaConfig,
-	conn: &PgConnection,
+	pool: &Pool,
 	deals: Vec<HubspotDeal>,
 	projects: Vec<HulaProject>,
 	log: Vec<HubspotProject>,
-) -> Result<(), String> {
-	println!("Henlo world");
-
-	/* iterate log, see what needs update */
-	for log1 in &log {
-		println!("log1 = {:?}", log1);
-
-		let h = projects.iter();
-		let a = h.filter(|x| x.id == log1.hula_id.to_string()).next();
-
-		if let Some(b) = a {
-			println!("Some(b) = {:?}", b);
-			let h2 = deals.iter();
-			let a2 = h2
-				.filter(|x| x.dealId.to_string() == log1.hubspot_id)
-				.next();
-
-			if let Some(b2) = a2 {
-				println!("Some(b2) = {:?}", b2);
-
-				let palvelut = match &b2.properties.palvelut {
-					Some(x) => Some(x.value.clone()),
-					None => None,
-				};
-
-				if b.name != b2.properties.dealname.value || b.description != palvelut {
-					println!(
-						"updating {} {}",
-						b.id.clone(),
-						b2.properties.dealname.value.clone()
-					);
-
-					let _ = update_hula_project(
-						config,
-						b.id.clone(),
-						b2.properties.dealname.value.clone(),
-						palvelut,
-					)
-					.await;
-				}
+) -> Result<usize, String> {
+	let changes = changeset::diff(&deals, &log, &projects, |deal, _, project| {
+		let palvelut = deal.properties.palvelut.as_ref().map(|x| &x.value);
+		project.name != deal.properties.dealname.value || project.description.as_ref() != palvelut
+	});
+
+	metrics::records("hubspot", "fetched", deals.len());
+	metrics::records("hubspot", "skipped", changes.unchanged.len());
+
+	trace!(
+		"{} to create, {} to update, {} unchanged, {} orphaned",
+		changes.create.len(),
+		changes.update.len(),
+		changes.unchanged.len(),
+		changes.orphaned.len()
+	);
+
+	let jobs = changes
+		.update
+		.iter()
+		.map(Job::Update)
+		.chain(changes.create.iter().map(Job::Create));
+
+	let results = stream::iter(jobs)
+		.map(|job| async {
+			let id = job.crm_key();
+			let action = job.action();
+			(id, action, run_job(config, pool, job).await)
+		})
+		.buffer_unordered(config.concurrency)
+		.collect::<Vec<_>>()
+		.await;
+
+	let mut done = 0;
+
+	for (id, action, result) in results {
+		match result {
+			Ok(()) => {
+				done += 1;
+				metrics::records("hubspot", action, 1);
+			}
+			Err(e) => {
+				error!("Deal {}: {}", id, e);
+				metrics::records("hubspot", "failed", 1);
 			}
 		}
 	}
 
-	/* iterate deals, see what needs insert */
-	for deal in &deals {
-		println!("deal = {:?}", deal);
-		let mut h = log.iter();
-		if h.any(|x| x.hubspot_id == deal.dealId.to_string()) == false {
-			println!("inserting {:?}", deal.properties.dealname.value);
-
-			let palvelut = match &deal.properties.palvelut {
-				Some(x) => Some(x.value.clone()),
-				None => None,
-			};
+	Ok(done)
+}
+
+/// One Hula call of a sync run, see `do_process2`.
+enum Job<'a> {
+	Update(&'a Mapped<'a, HubspotDeal, HubspotProject>),
+	Create(&'a Create<'a, HubspotDeal, HubspotProject>),
+}
 
-			let added = insert_hula_project(&config, deal.properties.dealname.value.clone(), palvelut).await;
+impl Job<'_> {
+	fn crm_key(&self) -> String {
+		match self {
+			Job::Update(change) => change.record.crm_key(),
+			Job::Create(change) => change.record.crm_key(),
+		}
+	}
+
+	/// What a successful run of the job did to the deal, for metrics.
+	fn action(&self) -> &'static str {
+		match self {
+			Job::Update(_) => "updated",
+			Job::Create(_) => "created",
+		}
+	}
+}
 
-			let my_uuid = Uuid::parse_str(&added.expect("no way")).expect("crash here");
+async fn run_job(config: &HulaConfig, pool: &Pool, job: Job<'_>) -> Result<(), String> {
+	match job {
+		/* update projects whose deal has changed */
+		Job::Update(change) => {
+			let deal = change.record;
 
-			let _ = insert_hubspot_log(
-				&conn,
-				my_uuid,
-				deal.dealId.to_string(),
+			let palvelut = deal.properties.palvelut.as_ref().map(|x| x.value.clone());
+
+			trace!(
+				"Updating Hula project {} from deal {}",
+				change.project.id.clone(),
+				deal.properties.dealname.value.clone()
+			);
+
+			let updated = update_hula_project(
+				config,
+				change.project.id.clone(),
 				deal.properties.dealname.value.clone(),
+				palvelut,
 			)
 			.await;
+
+			match updated {
+				Ok(_) => Ok(()),
+				Err(e) => Err(format!("update_hula_project failed: {}", e)),
+			}
 		}
-	}
 
-	Ok(())
+		/* create projects for new deals */
+		Job::Create(change) => {
+			let deal = change.record;
+
+			let reserved_id = match change.pending {
+				Some(row) => row.id,
+				None => {
+					let reserved = insert_hubspot_log(
+						pool,
+						deal.dealId.to_string(),
+						deal.properties.dealname.value.clone(),
+					)
+					.await;
+
+					match reserved {
+						Ok(id) => id,
+						Err(e) => return Err(format!("insert_hubspot_log failed: {}", e)),
+					}
+				}
+			};
+
+			let palvelut = deal.properties.palvelut.as_ref().map(|x| x.value.clone());
+
+			let added = insert_hula_project(
+				config,
+				deal.properties.dealname.value.clone(),
+				palvelut,
+				reserved_id.to_string(),
+			)
+			.await;
+
+			let added = match added {
+				Ok(id) => id,
+				Err(e) => return Err(format!("insert_hula_project failed: {}", e)),
+			};
+
+			let my_uuid = match Uuid::parse_str(&added) {
+				Ok(id) => id,
+				Err(e) => return Err(format!("Invalid Hula project id {}: {}", &added, e)),
+			};
+
+			/* a pending row is confirmed by external id on the next run */
+			if let Err(e) =
+				update_hubspot_log(pool, reserved_id, Some(my_uuid), sync_status::SYNCED).await
+			{
+				warn!("update_hubspot_log failed: {}", e);
+			}
+
+			Ok(())
+		}
+	}
 }
 
+/// Reserves a pending mapping row for a deal before its Hula project is
+/// created. The returned row id doubles as the project's external id.
 async fn insert_hubspot_log(
-	conn: &PgConnection,
-	hula_id: uuid::Uuid,
+	pool: &Pool,
 	hubspot_id: String,
 	name: String,
-) -> Result<(), String> {
+) -> Result<uuid::Uuid, String> {
 	use crate::schema::hubspot_projects::dsl::hubspot_projects;
 
 	let new_project = HubspotProject {
 		id: uuid::Uuid::new_v4(),
-		hula_id: hula_id,
-		hubspot_id: hubspot_id,
+		hula_id: None,
+		hubspot_id,
 		name: name.clone(),
 		updated_by: "hulasync".to_string(),
+		sync_status: sync_status::PENDING.to_string(),
 	};
-	println!("Inserting data");
+	let rows_inserted = db::run(pool, move |conn| {
+		diesel::insert_into(hubspot_projects)
+			.values(&new_project)
+			.get_result::<HubspotProject>(conn)
+	})
+	.await?;
+
+	match rows_inserted {
+		Ok(row) => Ok(row.id),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+async fn update_hubspot_log(
+	pool: &Pool,
+	id: uuid::Uuid,
+	hula_id: Option<uuid::Uuid>,
+	status: &str,
+) -> Result<(), String> {
+	use crate::schema::hubspot_projects::dsl;
+
+	let status = status.to_string();
+	let updated = db::run(pool, move |conn| {
+		diesel::update(dsl::hubspot_projects.find(id))
+			.set((dsl::hula_id.eq(hula_id), dsl::sync_status.eq(status)))
+			.execute(conn)
+	})
+	.await?;
+
+	match updated {
+		Ok(_) => Ok(()),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+async fn delete_hubspot_log(pool: &Pool, id: uuid::Uuid) -> Result<(), String> {
+	use crate::schema::hubspot_projects::dsl::hubspot_projects;
+
+	let deleted = db::run(pool, move |conn| {
+		diesel::delete(hubspot_projects.find(id)).execute(conn)
+	})
+	.await?;
+
+	match deleted {
+		Ok(_) => Ok(()),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+/// Sets mapping rows marked stale by `reconcile` back to synced when their
+/// deal is returned again.
+async fn restore_stale_hubspot_log(
+	pool: &Pool,
+	deals: &[HubspotDeal],
+	mut log: Vec<HubspotProject>,
+) -> Result<Vec<HubspotProject>, String> {
+	let returned: HashSet<String> = deals.iter().map(|x| x.dealId.to_string()).collect();
+
+	for row in log
+		.iter_mut()
+		.filter(|x| x.sync_status == sync_status::STALE)
+		.filter(|x| returned.contains(&x.hubspot_id))
+	{
+		update_hubspot_log(pool, row.id, row.hula_id, sync_status::SYNCED).await?;
+		trace!(
+			"Restored stale mapping for HubSpot deal {}.",
+			&row.hubspot_id
+		);
+
+		row.sync_status = sync_status::SYNCED.to_string();
+	}
+
+	Ok(log)
+}
 
-	let rows_inserted = diesel::insert_into(hubspot_projects)
-		.values(&new_project)
-		.get_result::<HubspotProject>(conn);
+/// Completes mapping rows left pending by an interrupted run, using the
+/// external id the Hula project was created with.
+async fn reconcile_pending_hubspot_log(
+	pool: &Pool,
+	projects: &[HulaProject],
+	mut log: Vec<HubspotProject>,
+) -> Result<Vec<HubspotProject>, String> {
+	for row in log
+		.iter_mut()
+		.filter(|x| x.sync_status == sync_status::PENDING)
+	{
+		let project = match find_by_external_id(projects, &row.id) {
+			Some(project) => project,
+			None => continue,
+		};
+
+		let hula_id = match Uuid::parse_str(&project.id) {
+			Ok(id) => id,
+			Err(e) => {
+				warn!("Invalid Hula project id {}: {}", &project.id, e);
+				continue;
+			}
+		};
+
+		update_hubspot_log(pool, row.id, Some(hula_id), sync_status::SYNCED).await?;
 
-	println!("{:?}", rows_inserted);
-	if rows_inserted.is_ok() {
-		println!("\nProject added successfully.\n");
-		return Ok(());
+		row.hula_id = Some(hula_id);
+		row.sync_status = sync_status::SYNCED.to_string();
 	}
 
-	return Err("failed".to_string());
+	Ok(log)
+}
+
+/// Applies the orphan policy to mapping rows whose Hula project has been
+/// deleted. HubSpot deals are always fetched in full, so recreated and
+/// re-imported deals are picked up by the insert pass of the same run.
+/// Returns the remaining rows and the policy applied, as for Odoo.
+async fn handle_orphaned_hubspot_log(
+	pool: &Pool,
+	projects: &[HulaProject],
+	log: Vec<HubspotProject>,
+	policy: OrphanPolicy,
+) -> Result<(Vec<HubspotProject>, OrphanPolicy), String> {
+	let index = ProjectIndex::new(projects);
+	let orphaned: HashSet<uuid::Uuid> = changeset::orphaned(&log, &index)
+		.iter()
+		.map(|x| x.id)
+		.collect();
+
+	let synced = log
+		.iter()
+		.filter(|x| x.sync_status == sync_status::SYNCED)
+		.count();
+	let policy = orphans::guard(policy, "HubSpot", projects.len(), synced, orphaned.len());
+
+	let mut remaining = Vec::with_capacity(log.len());
+
+	for mut row in log {
+		if !orphaned.contains(&row.id) {
+			remaining.push(row);
+			continue;
+		}
+
+		let hula_id = row.hula_id.map(|x| x.to_string()).unwrap_or_default();
+
+		warn!(
+			"Hula project {} for HubSpot deal {} no longer exists, policy {:?}.",
+			&hula_id, &row.hubspot_id, policy
+		);
+
+		match policy {
+			OrphanPolicy::Ignore => remaining.push(row),
+			OrphanPolicy::Recreate => {
+				update_hubspot_log(pool, row.id, None, sync_status::PENDING).await?;
+				row.hula_id = None;
+				row.sync_status = sync_status::PENDING.to_string();
+				remaining.push(row);
+			}
+			OrphanPolicy::Reimport => {
+				delete_hubspot_log(pool, row.id).await?;
+			}
+			OrphanPolicy::Remove => {
+				update_hubspot_log(pool, row.id, row.hula_id, sync_status::REMOVED).await?;
+				row.sync_status = sync_status::REMOVED.to_string();
+				remaining.push(row);
+			}
+		}
+	}
+
+	let applied = if orphaned.is_empty() {
+		OrphanPolicy::Ignore
+	} else {
+		policy
+	};
+
+	Ok((remaining, applied))
 }
 
 /*
@@ -0,0 +1,312 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Instant;
+
+use crate::error::{self, SyncError};
+use crate::hulautils::{get_hula_skills, HulaConfig, Skill};
+use crate::metrics;
+
+/// Keeps the options of a multi-select deal property in step with Hula's
+/// skill catalogue. Enabled by naming the property in
+/// `HUBSPOT_SKILL_PROPERTY`; the property is created if it does not exist.
+/// With `HUBSPOT_SKILL_HIDE_REMOVED=true` options of skills deleted in Hula
+/// are hidden.
+pub struct SkillPropertyConfig {
+	pub name: String,
+	pub hide_removed: bool,
+}
+
+impl SkillPropertyConfig {
+	pub fn from_env() -> Option<SkillPropertyConfig> {
+		let name = std::env::var("HUBSPOT_SKILL_PROPERTY").ok()?;
+		if name.trim().is_empty() {
+			return None;
+		}
+
+		let hide_removed = std::env::var("HUBSPOT_SKILL_HIDE_REMOVED")
+			.map(|v| v == "true" || v == "1")
+			.unwrap_or(false);
+
+		Some(SkillPropertyConfig {
+			name: name.trim().to_string(),
+			hide_removed,
+		})
+	}
+}
+
+/// An option of a HubSpot enumeration property. Options created here use
+/// the Hula skill id as `value`, so a renamed skill keeps its option.
+#[allow(non_snake_case)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct PropertyOption {
+	label: String,
+	value: String,
+	#[serde(default)]
+	displayOrder: i32,
+	#[serde(default)]
+	hidden: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Property {
+	options: Vec<PropertyOption>,
+}
+
+#[derive(Default)]
+struct Changes {
+	added: Vec<String>,
+	renamed: Vec<String>,
+	hidden: Vec<String>,
+}
+
+impl Changes {
+	fn is_empty(&self) -> bool {
+		self.added.is_empty() && self.renamed.is_empty() && self.hidden.is_empty()
+	}
+}
+
+pub async fn sync_skills_to_hubspot(
+	config: &HulaConfig,
+	property: &SkillPropertyConfig,
+) -> Result<(), String> {
+	let skills = get_hula_skills(config).await?;
+
+	let existing = match get_property(&property.name).await? {
+		Some(item) => item.options,
+		None => {
+			create_property(&property.name, &skills).await?;
+			info!(
+				"Created HubSpot property {} with {} skills.",
+				&property.name,
+				skills.len()
+			);
+			return Ok(());
+		}
+	};
+
+	let (options, changes) = plan_options(existing, &skills, property.hide_removed);
+
+	if changes.is_empty() {
+		return Ok(());
+	}
+
+	update_options(&property.name, &options).await?;
+
+	info!(
+		"HubSpot property {}: added {:?}, renamed {:?}, hidden {:?}",
+		&property.name, changes.added, changes.renamed, changes.hidden
+	);
+
+	Ok(())
+}
+
+/// Applies the skill catalogue to the current options. Options whose value
+/// is not a skill id were added by hand in HubSpot and are left as they are.
+fn plan_options(
+	mut options: Vec<PropertyOption>,
+	skills: &[Skill],
+	hide_removed: bool,
+) -> (Vec<PropertyOption>, Changes) {
+	let mut changes = Changes::default();
+	let mut next_order = options.iter().map(|x| x.displayOrder).max().unwrap_or(-1) + 1;
+
+	for skill in skills {
+		let value = skill.id.to_string();
+
+		match options.iter_mut().find(|x| x.value == value) {
+			Some(option) => {
+				if option.label != skill.label {
+					changes
+						.renamed
+						.push(format!("{} -> {}", &option.label, &skill.label));
+					option.label = skill.label.clone();
+				}
+				if option.hidden {
+					changes.added.push(skill.label.clone());
+					option.hidden = false;
+				}
+			}
+			None => {
+				changes.added.push(skill.label.clone());
+				options.push(PropertyOption {
+					label: skill.label.clone(),
+					value,
+					displayOrder: next_order,
+					hidden: false,
+				});
+				next_order += 1;
+			}
+		}
+	}
+
+	if hide_removed {
+		let ids: HashSet<String> = skills.iter().map(|x| x.id.to_string()).collect();
+
+		for option in options.iter_mut() {
+			let ours = uuid::Uuid::parse_str(&option.value).is_ok();
+			if ours && !option.hidden && !ids.contains(&option.value) {
+				changes.hidden.push(option.label.clone());
+				option.hidden = true;
+			}
+		}
+	}
+
+	(options, changes)
+}
+
+fn property_url(path: &str) -> Result<String, SyncError> {
+	let hubspot_key = error::env("HUBSPOT_API_KEY")?;
+
+	Ok(format!(
+		"https://api.hubapi.com/crm/v3/properties/deals{}?hapikey={}",
+		path, hubspot_key
+	))
+}
+
+async fn get_property(name: &str) -> Result<Option<Property>, String> {
+	let client = reqwest::Client::new();
+	let started = Instant::now();
+	let response = client
+		.get(property_url(&format!("/{}", name))?)
+		.send()
+		.await;
+	metrics::request("hubspot", started, &response);
+
+	let response = match response {
+		Ok(r) => r,
+		Err(e) => return Err(format!("Reading HubSpot property {} failed: {}", name, e)),
+	};
+
+	if response.status() == reqwest::StatusCode::NOT_FOUND {
+		return Ok(None);
+	}
+
+	let data = match response.error_for_status() {
+		Ok(r) => r.json::<Property>().await,
+		Err(e) => return Err(format!("Reading HubSpot property {} failed: {}", name, e)),
+	};
+
+	match data {
+		Ok(data) => Ok(Some(data)),
+		Err(e) => Err(format!("HubSpot property {} was not valid: {}", name, e)),
+	}
+}
+
+async fn create_property(name: &str, skills: &[Skill]) -> Result<(), String> {
+	let (options, _) = plan_options(vec![], skills, false);
+
+	let body = serde_json::json!({
+		"name": name,
+		"label": "Hula skills",
+		"groupName": "dealinformation",
+		"type": "enumeration",
+		"fieldType": "checkbox",
+		"options": options,
+	});
+
+	let client = reqwest::Client::new();
+	let started = Instant::now();
+	let response = client.post(property_url("")?).json(&body).send().await;
+	metrics::request("hubspot", started, &response);
+
+	match response.and_then(|r| r.error_for_status()) {
+		Ok(_) => Ok(()),
+		Err(e) => Err(format!("Creating HubSpot property {} failed: {}", name, e)),
+	}
+}
+
+/// Replaces the options of the property. HubSpot drops options left out of
+/// the list, which is why removed skills are hidden rather than omitted.
+async fn update_options(name: &str, options: &[PropertyOption]) -> Result<(), String> {
+	let body = serde_json::json!({ "options": options });
+
+	let client = reqwest::Client::new();
+	let started = Instant::now();
+	let response = client
+		.patch(property_url(&format!("/{}", name))?)
+		.json(&body)
+		.send()
+		.await;
+	metrics::request("hubspot", started, &response);
+
+	match response.and_then(|r| r.error_for_status()) {
+		Ok(_) => Ok(()),
+		Err(e) => Err(format!("Updating HubSpot property {} failed: {}", name, e)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn skill(label: &str) -> Skill {
+		Skill {
+			id: uuid::Uuid::new_v4(),
+			label: label.to_string(),
+			aliases: vec![],
+		}
+	}
+
+	fn option(label: &str, value: &str, order: i32) -> PropertyOption {
+		PropertyOption {
+			label: label.to_string(),
+			value: value.to_string(),
+			displayOrder: order,
+			hidden: false,
+		}
+	}
+
+	#[test]
+	fn adds_new_skills_after_existing_options() {
+		let rust = skill("Rust");
+		let options = vec![option("Other", "other", 3)];
+
+		let (options, changes) = plan_options(options, &[rust], false);
+
+		assert_eq!(changes.added, vec!["Rust"]);
+		assert_eq!(options.len(), 2);
+		assert_eq!(options[1].label, "Rust");
+		assert_eq!(options[1].displayOrder, 4);
+	}
+
+	#[test]
+	fn relabels_renamed_skills() {
+		let rust = skill("Rust");
+		let options = vec![option("rust-lang", &rust.id.to_string(), 0)];
+
+		let (options, changes) = plan_options(options, &[rust], false);
+
+		assert_eq!(changes.renamed, vec!["rust-lang -> Rust"]);
+		assert!(changes.added.is_empty());
+		assert_eq!(options.len(), 1);
+		assert_eq!(options[0].label, "Rust");
+	}
+
+	#[test]
+	fn hides_removed_skills_only_when_asked() {
+		let removed = uuid::Uuid::new_v4().to_string();
+		let options = vec![option("Cobol", &removed, 0), option("Other", "other", 1)];
+
+		let (kept, changes) = plan_options(options.clone(), &[], false);
+		assert!(changes.is_empty());
+		assert!(kept.iter().all(|x| !x.hidden));
+
+		let (hidden, changes) = plan_options(options, &[], true);
+		assert_eq!(changes.hidden, vec!["Cobol"]);
+		assert!(hidden[0].hidden);
+		assert!(!hidden[1].hidden, "options added by hand are left alone");
+	}
+
+	#[test]
+	fn shows_a_hidden_skill_again() {
+		let rust = skill("Rust");
+		let mut hidden = option("Rust", &rust.id.to_string(), 0);
+		hidden.hidden = true;
+
+		let (options, changes) = plan_options(vec![hidden], &[rust], true);
+
+		assert_eq!(changes.added, vec!["Rust"]);
+		assert!(!options[0].hidden);
+	}
+}
@@ -1 +1,3 @@
 pub mod odoo_module;
+pub mod reverse_sync;
+pub mod validation;
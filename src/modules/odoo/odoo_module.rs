@@ -1,17 +1,42 @@
-use chrono::NaiveDate;
-use diesel::{prelude::*, PgConnection};
-use log::{error, trace};
-use reqwest::StatusCode;
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use futures::stream::{self, StreamExt};
+use log::{error, info, trace, warn};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
+use crate::changeset::{self, Create, CrmRecord, Mapped, Mapping, ProjectIndex};
+use crate::conflicts;
+use crate::db;
+use crate::dead_letter;
+use crate::error::{self, SyncError};
+use crate::hulautils;
 use crate::hulautils::HulaConfig;
-use crate::hulautils::{get_hula_projects, HulaProject};
+use crate::hulautils::{
+	find_by_external_id, get_hula_projects, get_hula_skill_levels, get_hula_skills, HulaProject,
+	Skill, SkillScopeLevel,
+};
+use crate::metrics;
 use crate::models::hula_call_log::HulaCallLog;
 use crate::models::odoo_call_log::OdooCallLog;
-use crate::models::odoo_project::OdooProject;
-
-use std::process::Command;
+use crate::models::odoo_project::{OdooProject, Pool};
+use crate::models::sync_conflict::{self, SyncConflict};
+use crate::models::sync_dead_letter;
+use crate::models::sync_status;
+use crate::orphans::{self, OrphanPolicy};
+use crate::reconcile::{self, Category, Issue, SourceReport};
+use crate::retries;
+
+use super::reverse_sync::{self, Field, Owner, ReverseConfig, Side};
+use super::validation;
+
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::process::{Output, Stdio};
 use std::str;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct OdooProjectHeader {
@@ -20,6 +45,8 @@ pub struct OdooProjectHeader {
 	description: String,
 	visible: bool,
 	needs: Vec<OdooProjectNeed>,
+	#[serde(default)]
+	write_date: Option<NaiveDateTime>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -28,6 +55,8 @@ pub struct OdooProjectNeed {
 	nbr: i32,
 	begin: NaiveDate,
 	end: Option<NaiveDate>,
+	#[serde(default)]
+	percentage: Option<i32>,
 	skills: Vec<OdooProjectNeedSkill>,
 }
 
@@ -36,17 +65,21 @@ pub struct OdooProjectNeedSkill {
 	skill: String,
 	level: Option<String>,
 	min_years: Option<f64>,
+	#[serde(default)]
+	max_years: Option<f64>,
 	mandatory: bool,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct HulaProjectStructureData {
 	pub name: String,
 	pub is_hidden: bool,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub external_id: Option<String>,
 	pub needs: Vec<HulaProjectStructureNeedData>,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct HulaProjectStructureNeedData {
 	pub label: String,
 	pub count_of_users: i32,
@@ -56,7 +89,7 @@ pub struct HulaProjectStructureNeedData {
 	pub skills: Vec<HulaProjectStructureNeedSkillData>,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct HulaProjectStructureNeedSkillData {
 	pub skill_label: String,
 	pub skillscopelevel_label: Option<String>,
@@ -65,18 +98,12 @@ pub struct HulaProjectStructureNeedSkillData {
 	pub mandatory: bool,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-pub struct Skill {
-	pub id: uuid::Uuid,
-	pub label: String,
-	pub aliases: Vec<String>
-}
-
 impl From<&OdooProjectHeader> for HulaProjectStructureData {
 	fn from(project: &OdooProjectHeader) -> HulaProjectStructureData {
 		HulaProjectStructureData {
 			name: project.name.clone(),
 			is_hidden: !project.visible,
+			external_id: None,
 			needs: project
 				.needs
 				.iter()
@@ -85,7 +112,7 @@ impl From<&OdooProjectHeader> for HulaProjectStructureData {
 					count_of_users: x.nbr,
 					begin_time: x.begin,
 					end_time: x.end,
-					percentage: Some(100),
+					percentage: x.percentage,
 					skills: x
 						.skills
 						.iter()
@@ -93,7 +120,7 @@ impl From<&OdooProjectHeader> for HulaProjectStructureData {
 							skill_label: y.skill.clone(),
 							skillscopelevel_label: y.level.clone(),
 							min_years: y.min_years,
-							max_years: None,
+							max_years: y.max_years,
 							mandatory: y.mandatory,
 						})
 						.collect(),
@@ -103,11 +130,67 @@ impl From<&OdooProjectHeader> for HulaProjectStructureData {
 	}
 }
 
+/// SHA-256 of the structure as it is sent to Hula on update.
+fn content_hash(data: &HulaProjectStructureData) -> String {
+	let json = serde_json::to_string(data).unwrap_or_default();
+	format!("{:x}", Sha256::digest(json.as_bytes()))
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ProjectMatch {
 	pub id: i32,
 	pub matches: i32,
 	pub link: String,
+	/// Summary of the matched candidates. Only set when it changed since it
+	/// was last written to the lead.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub summary: Option<String>,
+	#[serde(skip)]
+	pub mapping_id: uuid::Uuid,
+	#[serde(skip)]
+	pub hula_id: uuid::Uuid,
+	#[serde(skip)]
+	pub summary_hash: Option<String>,
+}
+
+/// A candidate Hula matched to a project structure.
+#[derive(Deserialize, Debug)]
+pub struct HulaMatch {
+	pub name: String,
+	/// Match score in percent.
+	#[serde(default)]
+	pub score: Option<f64>,
+	#[serde(default)]
+	pub availability: Option<String>,
+}
+
+/// Where the summary of matched candidates is written on the lead.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SummaryTarget {
+	Off,
+	/// A text field of `crm.lead`.
+	Field(String),
+	/// A chatter message on the lead.
+	Note,
+}
+
+impl SummaryTarget {
+	/// Parses ODOO_MATCH_SUMMARY: `off` (default), `note` or `field:<name>`.
+	fn from_env() -> SummaryTarget {
+		let value = std::env::var("ODOO_MATCH_SUMMARY").unwrap_or_default();
+
+		match value.trim() {
+			"" | "off" => SummaryTarget::Off,
+			"note" => SummaryTarget::Note,
+			other => match other.strip_prefix("field:") {
+				Some(field) if !field.is_empty() => SummaryTarget::Field(field.to_string()),
+				_ => {
+					warn!("Unknown ODOO_MATCH_SUMMARY '{}', using off.", other);
+					SummaryTarget::Off
+				}
+			},
+		}
+	}
 }
 
 #[derive(Deserialize, Debug)]
@@ -116,114 +199,547 @@ pub struct HulaProjectStructureResponse {
 	pub matches: i32,
 }
 
+/// A project structure with its content, as returned by
+/// GET /api/projectstructures/{id}.
+#[derive(Deserialize, Debug)]
+pub struct HulaProjectStructure {
+	pub matches: i32,
+	#[serde(default)]
+	pub updated_at: Option<String>,
+	#[serde(flatten)]
+	pub data: HulaProjectStructureData,
+}
+
+/// Hula-side edits to write to an Odoo lead, see `reverse_sync`.
+#[derive(Serialize, Debug)]
+pub struct LeadUpdate {
+	pub id: i32,
+	pub values: serde_json::Map<String, serde_json::Value>,
+	#[serde(skip)]
+	pub mapping_id: uuid::Uuid,
+	/// Hash of the lead's structure once the values are written.
+	#[serde(skip)]
+	pub content_hash: String,
+	/// The structure both sides have once the values are written.
+	#[serde(skip)]
+	pub snapshot: Option<String>,
+	/// Resolved conflicts applied by writing the values.
+	#[serde(skip)]
+	pub resolved: Vec<uuid::Uuid>,
+}
+
 pub struct OdooConfig {
 	pub odoo_url: String,
 	pub odoo_db: String,
 	pub odoo_uid: String,
 	pub odoo_pw: String,
+	/// Seconds a Python script may run before it is killed.
+	pub script_timeout: u64,
+	/// Anchor text of the Hula link written to leads.
+	pub link_text: String,
+	/// Anchor text by Odoo language code, e.g. `fi_FI`.
+	pub link_texts: HashMap<String, String>,
+	pub match_summary: SummaryTarget,
+	/// Text field of `crm.lead` that shows why a lead was not synced.
+	pub error_field: Option<String>,
+	/// Field of `crm.lead` holding a need's allocation percentage.
+	pub percentage_field: String,
+	/// Fields of `crm.lead` holding each skill's maximum years. `{n}` is
+	/// replaced with the skill slot, 1 to 4.
+	pub max_years_field: String,
+	/// Percentage of needs whose lead leaves it empty.
+	pub default_percentage: Option<i32>,
+	/// Maximum years of skills whose lead leaves it empty.
+	pub default_max_years: Option<f64>,
 }
 
-fn get_config() -> OdooConfig {
+fn get_config() -> Result<OdooConfig, SyncError> {
 	let config = OdooConfig {
-		odoo_url: std::env::var("ODOO_URL").expect("ODOO_URL must be set"),
-		odoo_db: std::env::var("ODOO_DB").expect("ODOO_DB must be set"),
-		odoo_uid: std::env::var("ODOO_USERNAME").expect("ODOO_USERNAME must be set"),
-		odoo_pw: std::env::var("ODOO_PASSWORD").expect("ODOO_PASSWORD must be set"),
+		odoo_url: error::env("ODOO_URL")?,
+		odoo_db: error::env("ODOO_DB")?,
+		odoo_uid: error::env("ODOO_USERNAME")?,
+		odoo_pw: error::env("ODOO_PASSWORD")?,
+		script_timeout: std::env::var("ODOO_SCRIPT_TIMEOUT")
+			.ok()
+			.and_then(|v| v.parse().ok())
+			.unwrap_or(300),
+		link_text: std::env::var("ODOO_LINK_TEXT")
+			.unwrap_or_else(|_| "Open project in Hula".to_string()),
+		link_texts: parse_link_texts(&std::env::var("ODOO_LINK_TEXTS").unwrap_or_default()),
+		match_summary: SummaryTarget::from_env(),
+		error_field: std::env::var("ODOO_ERROR_FIELD")
+			.ok()
+			.filter(|x| !x.trim().is_empty()),
+		percentage_field: std::env::var("ODOO_PERCENTAGE_FIELD")
+			.unwrap_or_else(|_| "x_studio_percentage".to_string()),
+		max_years_field: std::env::var("ODOO_MAX_YEARS_FIELD")
+			.unwrap_or_else(|_| "x_studio_maximum_years_{n}".to_string()),
+		default_percentage: match std::env::var("ODOO_DEFAULT_PERCENTAGE") {
+			Ok(v) => v.parse().ok(),
+			Err(_) => Some(100),
+		},
+		default_max_years: std::env::var("ODOO_DEFAULT_MAX_YEARS")
+			.ok()
+			.and_then(|v| v.parse().ok()),
 	};
 
-	config
+	Ok(config)
 }
 
-pub async fn do_process(config: &HulaConfig, conn: &PgConnection) -> Result<(), String> {
+/// Parses `fi_FI=Avaa Hulassa;sv_SE=Öppna i Hula` into language code and
+/// anchor text pairs.
+fn parse_link_texts(value: &str) -> HashMap<String, String> {
+	value
+		.split(';')
+		.filter_map(|x| x.split_once('='))
+		.map(|(lang, text)| (lang.trim().to_string(), text.trim().to_string()))
+		.filter(|(lang, text)| !lang.is_empty() && !text.is_empty())
+		.collect()
+}
+
+/// Runs one Odoo sync. Returns the number of leads synced or failed.
+pub async fn do_process(config: &HulaConfig, pool: &Pool) -> Result<usize, SyncError> {
 	trace!("Processing Odoo interface.");
 
-	let last_run = startup(&conn).await?;
+	let last_run = startup(pool).await?;
 
 	if let Some(last_run) = last_run {
 		trace!("Last run was: {}", &last_run);
 	}
 
-	sync_skills_to_odoo(config, conn).await?;
+	let skills = sync_skills_to_odoo(config, pool).await?;
+	let levels = sync_levels_to_odoo(config, pool).await?;
+
+	let log = get_odoo_log(pool).await?;
+	trace!("Got Integration project descriptions: {}", log.len());
+
+	let hula_projects = get_hula_projects(config).await?;
+	trace!("Got Hula project descriptions: {}", hula_projects.len());
+
+	let log = reconcile_pending_odoo_log(pool, &hula_projects, log).await?;
+
+	// Leads to fetch even if they did not change since the last run.
+	let mut refetch: BTreeSet<i32> = BTreeSet::new();
+
+	let orphaned: Vec<i32> = changeset::orphaned(&log, &ProjectIndex::new(&hula_projects))
+		.iter()
+		.map(|x| x.odoo_id)
+		.collect();
+
+	let policy = OrphanPolicy::from_env();
+	let (log, applied) = handle_orphaned_odoo_log(pool, &hula_projects, log, policy).await?;
+
+	if applied.needs_refetch() {
+		trace!("Orphaned mappings found, fetching their Odoo leads.");
+		refetch.extend(orphaned);
+	}
+
+	let pending = log
+		.iter()
+		.filter(|x| x.sync_status == sync_status::PENDING)
+		.map(|x| x.odoo_id);
+
+	refetch.extend(pending);
+
+	let reverse = ReverseConfig::from_env();
+
+	if reverse.enabled {
+		refetch.extend(hula_changed_leads(&log, &hula_projects));
+	}
+
+	let mut conflicts: HashMap<uuid::Uuid, Vec<SyncConflict>> = HashMap::new();
+	let mut reopened = vec![];
+
+	for mut conflict in conflicts::unapplied(pool, "odoo").await? {
+		// Without reverse sync the Hula value cannot reach Odoo, and the
+		// next Odoo edit would undo it, so the conflict stays open.
+		if !reverse.enabled && conflict.status == sync_conflict::KEEP_HULA {
+			warn!(
+				"Conflict {} keeps the Hula value but ODOO_REVERSE_SYNC is off, reopening it.",
+				conflict.id
+			);
+			reopened.push(conflict.id);
+			conflict.status = sync_conflict::OPEN.to_string();
+		}
 
-	let odoo_deals = get_odoo_deals(&conn, last_run).await?;
+		conflicts
+			.entry(conflict.mapping_id)
+			.or_default()
+			.push(conflict);
+	}
+
+	conflicts::reopen(pool, reopened).await?;
+
+	let resolved = conflicts
+		.values()
+		.flatten()
+		.filter(|x| x.status != sync_conflict::OPEN)
+		.filter_map(|x| x.crm_id.parse::<i32>().ok());
+
+	refetch.extend(resolved);
+
+	let now = chrono::Utc::now().naive_utc();
+	let retries = retries::load(pool, "odoo").await?;
+
+	let due = retries
+		.iter()
+		.filter(|x| retries::is_due(x, now))
+		.filter_map(|x| x.crm_id.parse::<i32>().ok());
+
+	refetch.extend(due);
+
+	let waiting = retries
+		.iter()
+		.filter(|x| !retries::is_due(x, now))
+		.filter_map(|x| Some((x.crm_id.parse().ok()?, x.payload.clone())))
+		.collect();
+
+	let letters = dead_letter::load(pool, "odoo").await?;
+
+	let mut dead = HashSet::new();
+	let mut requeued = HashMap::new();
+	let mut overrides = HashMap::new();
+
+	for letter in &letters {
+		let id: i32 = match letter.crm_id.parse() {
+			Ok(v) => v,
+			Err(_) => continue,
+		};
+
+		if letter.status == sync_dead_letter::DEAD {
+			dead.insert(id);
+			continue;
+		}
+
+		requeued.insert(id, letter.attempts);
+		refetch.insert(id);
+
+		if letter.status == sync_dead_letter::EDITED {
+			match serde_json::from_str::<HulaProjectStructureData>(&letter.payload) {
+				Ok(data) => {
+					overrides.insert(id, data);
+				}
+				Err(e) => {
+					error!(
+						"Edited payload of lead {} is not a project structure: {}",
+						id, e
+					);
+					dead.insert(id);
+				}
+			}
+		}
+	}
+
+	if last_run.is_some() && !refetch.is_empty() {
+		trace!("Fetching unchanged Odoo leads again: {}", refetch.len());
+	}
+
+	let refetch: Vec<i32> = refetch.into_iter().collect();
+	let odoo_deals = get_odoo_deals(pool, last_run, &refetch).await?;
+	metrics::records("odoo", "fetched", odoo_deals.len());
 	trace!("No projects from odoo.");
 
-	if odoo_deals.len() > 0 {
+	let mut processed = 0;
+
+	if !odoo_deals.is_empty() {
 		trace!("Got Odoo unprocessed projects: {}", odoo_deals.len());
 
-		let hula_projects = get_hula_projects(&config).await?;
-		trace!("Got Hula project descriptions: {}", hula_projects.len());
+		let state = RunState {
+			reverse,
+			conflicts,
+			skills,
+			levels,
+			waiting,
+			dead,
+			overrides,
+		};
+
+		let log = restore_stale_odoo_log(pool, &odoo_deals, log).await?;
+		let outcome =
+			do_process_internal(config, pool, &state, odoo_deals, hula_projects, log).await?;
+		trace!("Processing resulted in matches: {}", outcome.matches.len());
+		processed = outcome.matches.len() + outcome.failed.len();
+
+		let mut failed = outcome.failed;
+
+		if !outcome.leads.is_empty() {
+			trace!("Writing Hula edits to Odoo leads: {}", outcome.leads.len());
+			failed.extend(put_odoo_leads(pool, &outcome.leads).await);
+		}
 
-		let log = get_odoo_log(&conn).await?;
-		trace!("Got Integration project descriptions: {}", log.len());
+		metrics::records("odoo", "failed", failed.len());
+		let failed_ids: HashSet<i32> = failed.iter().map(|x| x.id).collect();
 
-		let matches = do_process_internal(&config, &conn, odoo_deals, hula_projects, log).await?;
-		trace!("Processing resulted in matches: {}", matches.len());
+		for failure in failed {
+			let requeued = requeued.get(&failure.id).copied();
+			record_failure(pool, failure, requeued).await;
+		}
 
-		put_odoo_matches(&conn, matches).await?;
+		put_odoo_matches(pool, &outcome.matches, &outcome.invalid).await?;
+		store_summary_hashes(pool, &outcome.matches).await;
+
+		let synced: Vec<String> = outcome
+			.matches
+			.iter()
+			.filter(|x| !failed_ids.contains(&x.id))
+			.map(|x| x.id.to_string())
+			.collect();
+		if let Err(e) = retries::clear(pool, "odoo", synced.clone()).await {
+			error!("Clearing retries failed: {}", e);
+		}
+		if let Err(e) = dead_letter::clear(pool, "odoo", synced).await {
+			error!("Clearing dead letters failed: {}", e);
+		}
 	}
 
 	trace!("Odoo interface done.");
-	Ok(())
-}
-
-async fn sync_skills_to_odoo(config: &HulaConfig, conn: &PgConnection) -> Result<(), String> {
-	let hula_skills = get_skills_from_hula(config).await?;
-	put_skills_to_odoo(&hula_skills, conn).await?;
-	generate_skills_to_odoo_projects(&hula_skills, conn).await?;
-	Ok(())
+	Ok(processed)
 }
 
-async fn get_skills_from_hula(config: &HulaConfig) -> Result<Vec<Skill>, String> {
-	let request_url = format!("{}/api/skills", config.hula_url);
-	let client = reqwest::Client::new();
-	let response = match client
-		.get(&request_url)
-		.header("Cookie", format!("auth={}", config.cookie))
-		.send()
-		.await
-	{
-		Ok(res) => res,
-		Err(err) => return Err(err.to_string()),
+/// Compares Odoo leads, `odoo_projects` and Hula projects for the
+/// `reconcile` command, fixing the categories listed in `fix`.
+pub async fn reconcile(
+	config: &HulaConfig,
+	pool: &Pool,
+	projects: &[HulaProject],
+	fix: &[Category],
+) -> Result<SourceReport, String> {
+	let (deals, _) = fetch_odoo_deals(&get_config()?, None, &[]).await?;
+	let log = get_odoo_log(pool).await?;
+
+	let mut issues = vec![];
+
+	let pending: Vec<uuid::Uuid> = log
+		.iter()
+		.filter(|x| x.sync_status == sync_status::PENDING)
+		.map(|x| x.id)
+		.collect();
+
+	let log = if fix.contains(&Category::PendingMapping) {
+		reconcile_pending_odoo_log(pool, projects, log).await?
+	} else {
+		log
 	};
 
-	if response.status() == StatusCode::NO_CONTENT {
-		return Ok(Vec::new());
+	for row in log.iter().filter(|x| pending.contains(&x.id)) {
+		let fixed = row.sync_status == sync_status::SYNCED;
+		issues.push(Issue {
+			category: Category::PendingMapping,
+			source: "odoo",
+			crm_id: Some(row.odoo_id.to_string()),
+			hula_id: row.hula_id.map(|x| x.to_string()),
+			detail: if fixed {
+				"Project found by external id".to_string()
+			} else {
+				"No Hula project with this external id".to_string()
+			},
+			fixed,
+		});
 	}
 
-	let data = match response.error_for_status() {
-		Ok(r) => r.json::<Vec<Skill>>().await,
-		Err(err) => return Err(err.to_string()),
-	};
+	let policy = OrphanPolicy::from_env();
+	let fix_orphans = fix.contains(&Category::OrphanedMapping) && policy != OrphanPolicy::Ignore;
+	let fix_stale = fix.contains(&Category::StaleMapping);
+	let fix_drift = fix.contains(&Category::NameDrift);
+	let mut orphan_issues = vec![];
+
+	issues.extend(reconcile::find_duplicate_mappings("odoo", &log));
+
+	let index = ProjectIndex::new(projects);
+	let by_id: HashMap<i32, &OdooProjectHeader> = deals.iter().map(|x| (x.id, x)).collect();
+
+	for row in log.iter().filter(|x| x.sync_status == sync_status::SYNCED) {
+		let (hula_id, project) = match row.hula_id {
+			Some(id) => (id.to_string(), index.get(&id)),
+			None => continue,
+		};
+
+		let deal = by_id.get(&row.odoo_id).copied();
+
+		if project.is_none() {
+			orphan_issues.push(issues.len());
+			issues.push(Issue {
+				category: Category::OrphanedMapping,
+				source: "odoo",
+				crm_id: Some(row.odoo_id.to_string()),
+				hula_id: Some(hula_id.clone()),
+				detail: format!("Hula project for '{}' no longer exists", &row.name),
+				fixed: false,
+			});
+		}
+
+		if deal.is_none() {
+			let mut fixed = false;
+			if fix_stale {
+				update_odoo_log(pool, row.id, row.hula_id, sync_status::STALE).await?;
+				fixed = true;
+			}
+
+			issues.push(Issue {
+				category: Category::StaleMapping,
+				source: "odoo",
+				crm_id: Some(row.odoo_id.to_string()),
+				hula_id: Some(hula_id.clone()),
+				detail: format!(
+					"Lead '{}' is deleted or no longer has the fields needed for syncing",
+					&row.name
+				),
+				fixed,
+			});
+		}
+
+		if let (Some(project), Some(deal)) = (project, deal) {
+			if project.name != deal.name {
+				let mut fixed = false;
+				if fix_drift {
+					let data: HulaProjectStructureData = deal.into();
+					update_hula_project_odoo(pool, config, project.id.clone(), deal, &data).await?;
+					update_odoo_log_hash(pool, row.id, Some(&content_hash(&data)), snapshot(&data))
+						.await?;
+					fixed = true;
+				}
+
+				issues.push(Issue {
+					category: Category::NameDrift,
+					source: "odoo",
+					crm_id: Some(row.odoo_id.to_string()),
+					hula_id: Some(hula_id.clone()),
+					detail: format!("Odoo: '{}', Hula: '{}'", &deal.name, &project.name),
+					fixed,
+				});
+			}
+		}
+	}
+
+	let mapped: HashSet<i32> = log.iter().map(|x| x.odoo_id).collect();
+
+	for deal in &deals {
+		if !mapped.contains(&deal.id) {
+			issues.push(Issue {
+				category: Category::UnmappedCrm,
+				source: "odoo",
+				crm_id: Some(deal.id.to_string()),
+				hula_id: None,
+				detail: format!("Lead '{}' has no Hula project", &deal.name),
+				fixed: false,
+			});
+		}
+	}
 
-	match data {
-		Ok(data) => Ok(data),
-		Err(err) => Err(err.to_string()),
+	let mapped_hula_ids = log
+		.iter()
+		.filter_map(|x| x.hula_id)
+		.map(|x| x.to_string())
+		.collect();
+
+	if fix_orphans {
+		let (_, applied) = handle_orphaned_odoo_log(pool, projects, log, policy).await?;
+		for idx in orphan_issues {
+			issues[idx].fixed = applied != OrphanPolicy::Ignore;
+		}
 	}
+
+	Ok(SourceReport {
+		issues,
+		mapped_hula_ids,
+	})
 }
 
-async fn put_skills_to_odoo(skills: &Vec<Skill>, conn: &PgConnection) -> Result<(), String> {
-	let skills_json = match serde_json::to_string(skills) {
+async fn sync_skills_to_odoo(config: &HulaConfig, pool: &Pool) -> Result<Vec<Skill>, String> {
+	let hula_skills = get_hula_skills(config).await?;
+	put_skills_to_odoo(&hula_skills, pool).await?;
+	generate_skills_to_odoo_projects(&hula_skills, pool).await?;
+	Ok(hula_skills)
+}
+
+/// What `odoo_put_skills.py` or `odoo_put_levels.py` changed in Odoo.
+/// Records are keyed on the Hula id, so a renamed item keeps its record and
+/// a deleted one is archived.
+#[derive(Deserialize, Debug)]
+struct CatalogueSummary {
+	created: Vec<String>,
+	renamed: Vec<String>,
+	archived: Vec<String>,
+	restored: Vec<String>,
+	changed: Vec<String>,
+}
+
+impl CatalogueSummary {
+	fn is_empty(&self) -> bool {
+		self.created.is_empty()
+			&& self.renamed.is_empty()
+			&& self.archived.is_empty()
+			&& self.restored.is_empty()
+			&& self.changed.is_empty()
+	}
+}
+
+async fn put_skills_to_odoo(skills: &[Skill], pool: &Pool) -> Result<(), String> {
+	put_catalogue_to_odoo(
+		"src/modules/odoo/python/odoo_put_skills.py",
+		"skills",
+		skills,
+		pool,
+	)
+	.await
+}
+
+async fn put_catalogue_to_odoo<T: Serialize>(
+	script_path: &str,
+	what: &str,
+	items: &[T],
+	pool: &Pool,
+) -> Result<(), String> {
+	let items_json = match serde_json::to_string(items) {
 		Ok(it) => it,
 		Err(err) => return Err(err.to_string()),
 	};
 
-	let result = run_odoo_script(
-		String::from("src/modules/odoo/python/odoo_put_skills.py"),
-		conn,
-		&[skills_json],
-	)
-	.await;
+	let result = run_odoo_script(script_path.to_string(), pool, &items_json).await;
 
-	match result {
-		Ok(output) => println!("Following skills were created in Odoo: {}", output),
-		Err(e) => return Err(e),
+	let output = match result {
+		Ok(output) => output,
+		Err(e) => return Err(e.into()),
 	};
 
+	let summary: CatalogueSummary = match serde_json::from_value(output) {
+		Ok(v) => v,
+		Err(e) => return Err(format!("{} output was not valid: {}", script_path, e)),
+	};
+
+	if !summary.is_empty() {
+		info!(
+			"Odoo {}: created {:?}, renamed {:?}, archived {:?}, restored {:?}, changed {:?}",
+			what,
+			summary.created,
+			summary.renamed,
+			summary.archived,
+			summary.restored,
+			summary.changed
+		);
+	}
+
 	Ok(())
 }
 
-async fn generate_skills_to_odoo_projects(skills: &Vec<Skill>, conn: &PgConnection) -> Result<(), String> {
+/// Mirrors Hula's skill scope levels to the model of the leads' level
+/// fields. Returns the levels for validating outgoing needs.
+async fn sync_levels_to_odoo(
+	config: &HulaConfig,
+	pool: &Pool,
+) -> Result<Vec<SkillScopeLevel>, String> {
+	let levels = get_hula_skill_levels(config).await?;
+	put_catalogue_to_odoo(
+		"src/modules/odoo/python/odoo_put_levels.py",
+		"skill levels",
+		&levels,
+		pool,
+	)
+	.await?;
+	Ok(levels)
+}
+
+async fn generate_skills_to_odoo_projects(skills: &Vec<Skill>, pool: &Pool) -> Result<(), String> {
 	let skills_json = match serde_json::to_string(skills) {
 		Ok(it) => it,
 		Err(err) => return Err(err.to_string()),
@@ -231,8 +747,8 @@ async fn generate_skills_to_odoo_projects(skills: &Vec<Skill>, conn: &PgConnecti
 
 	let result = run_odoo_script(
 		String::from("src/modules/odoo/python/odoo_fill_project_skills.py"),
-		conn,
-		&[skills_json],
+		pool,
+		&skills_json,
 	)
 	.await;
 
@@ -241,440 +757,1167 @@ async fn generate_skills_to_odoo_projects(skills: &Vec<Skill>, conn: &PgConnecti
 			"Skills were generated for the following Odoo leads: {}",
 			output
 		),
-		Err(e) => return Err(e),
+		Err(e) => return Err(e.into()),
 	};
 
 	Ok(())
 }
 
-pub async fn run_odoo_script(
-	script_path: String,
-	conn: &PgConnection,
-	additional_params: &[String],
-) -> Result<String, String> {
-	let c = get_config();
-
-	let mut args = Vec::from([&c.odoo_url, &c.odoo_db, &c.odoo_uid, &c.odoo_pw]);
-	for param in additional_params {
-		args.push(&param)
-	}
-
-	let mut cmd = script_path.clone();
-	for arg in &args {
-		cmd.push_str(&format!(" {}", arg))
-	}
-
-	trace!("Running: python3 {}", cmd);
-
-	let mut python_args = Vec::from([&script_path]);
-	python_args.extend(args);
-	let output = Command::new("python3").args(&python_args).output();
-
-	// stdout, stderr
-	let result = match output {
-		Ok(x) => match str::from_utf8(&x.stdout) {
-			Ok(it) => {
-				// Check and handle stderr
-				let stderr: Option<String> = match str::from_utf8(&x.stderr) {
-					Ok(v) => {
-						if v.is_empty() {
-							None
-						} else {
-							Some(v.to_string())
-						}
-					}
-					Err(er) => Some(format!(
-						"Invalid UTF-8 sequence on stderr: {}",
-						er.to_string()
-					)),
-				};
-				// stdout, stderr
-				(Some(it.to_string()), stderr)
-			}
-			Err(e) => (
-				None,
-				Some(format!(
-					"Invalid UTF-8 sequence on stdout: {}",
-					e.to_string()
-				)),
-			),
-		},
-		Err(e) => (None, Some(format!("Python3 failed: {}", e.to_string()))),
-	};
+/// What every Odoo script prints to stdout.
+#[derive(Deserialize, Debug)]
+struct ScriptEnvelope {
+	ok: bool,
+	#[serde(default)]
+	data: serde_json::Value,
+	#[serde(default)]
+	error: Option<String>,
+}
 
-	let output_str = result.0;
-	let error = result.1;
+/// Why an Odoo script run failed.
+#[derive(Debug)]
+pub enum ScriptError {
+	/// `python3` could not be started or waited for.
+	Spawn(String),
+	/// The script was killed after running this many seconds.
+	Timeout(u64),
+	/// The script exited with a non-zero status.
+	Exit { code: Option<i32>, stderr: String },
+	/// Stdout was not a JSON envelope.
+	InvalidOutput { reason: String, stderr: String },
+	/// The script reported `ok: false`.
+	Failed(String),
+	/// The Odoo connection is not configured.
+	Config(String),
+}
 
-	if error.is_some() {
-		error!("{}", error.clone().unwrap())
+impl std::fmt::Display for ScriptError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			ScriptError::Spawn(e) => write!(f, "Python3 failed: {}", e),
+			ScriptError::Timeout(secs) => write!(f, "Script timed out after {} seconds", secs),
+			ScriptError::Exit { code, stderr } => match code {
+				Some(code) => write!(f, "Script exited with status {}: {}", code, stderr),
+				None => write!(f, "Script was terminated by a signal: {}", stderr),
+			},
+			ScriptError::InvalidOutput { reason, stderr } => {
+				write!(f, "Script output was not valid: {} {}", reason, stderr)
+			}
+			ScriptError::Failed(e) => write!(f, "Script failed: {}", e),
+			ScriptError::Config(e) => write!(f, "Odoo is not configured: {}", e),
+		}
 	}
+}
 
-	let ok = output_str.is_some();
+impl From<ScriptError> for String {
+	fn from(error: ScriptError) -> String {
+		error.to_string()
+	}
+}
 
-	let result = match output_str.clone() {
-		Some(str) => str,
-		_ => {
-			if error.is_some() {
-				error.unwrap()
-			} else {
-				format!("")
-			}
+/// Runs an Odoo script with `payload` on stdin and records the call in
+/// `odoo_call_log`. Returns the `data` of the script's envelope.
+pub async fn run_odoo_script(
+	script_path: String,
+	pool: &Pool,
+	payload: &str,
+) -> Result<serde_json::Value, ScriptError> {
+	let c = match get_config() {
+		Ok(c) => c,
+		Err(e) => return Err(ScriptError::Config(e.to_string())),
+	};
+
+	let result = call_odoo_script(&c, &script_path, payload).await;
+
+	let (ok, response) = match &result {
+		Ok(data) => (true, data.to_string()),
+		Err(e) => {
+			error!("{}", e);
+			(false, e.to_string())
 		}
 	};
 
 	let _ = write_odoo_call_log(
-		&conn,
+		pool,
 		&script_path,
 		Some(&c.odoo_url),
 		Some(&c.odoo_db),
 		Some(&c.odoo_uid),
-		Some(&c.odoo_pw),
-		match additional_params.get(0) {
-			Some(s) => Some(&s[..]),
-			None => None,
-		},
-		match additional_params.get(1) {
-			Some(s) => Some(&s[..]),
-			None => None,
-		},
+		None,
+		Some(payload),
+		None,
 		ok,
-		Some(&result),
+		Some(&response),
 	)
 	.await;
 
-	Ok(output_str.unwrap())
+	result
 }
 
-async fn get_odoo_deals(
-	conn: &PgConnection,
-	last_run: Option<i64>,
-) -> Result<Vec<OdooProjectHeader>, String> {
-	let c = get_config();
-
-	let last_run: String = match last_run {
-		Some(x) => x.to_string(),
-		None => "".to_string(),
-	};
+/// Runs an Odoo script and unwraps its envelope. Connection details are
+/// passed in the environment and `payload` is written to stdin, so neither
+/// shows up in the process list. A run only succeeds if the script exits
+/// with status 0 and prints an envelope with `ok: true`.
+async fn call_odoo_script(
+	c: &OdooConfig,
+	script_path: &str,
+	payload: &str,
+) -> Result<serde_json::Value, ScriptError> {
+	trace!("Running: python3 {}", script_path);
+
+	let started = Instant::now();
+	let output = run_python(c, script_path, payload).await;
+	let ok = output.as_ref().is_ok_and(|x| x.status.success());
+	metrics::odoo_script(script_path, started, ok);
+	let output = output?;
+
+	let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+
+	if !output.status.success() {
+		// The envelope usually explains the failure better than a traceback.
+		let envelope: Option<ScriptEnvelope> = serde_json::from_slice(&output.stdout).ok();
+		if let Some(ScriptEnvelope {
+			ok: false,
+			error: Some(e),
+			..
+		}) = envelope
+		{
+			return Err(ScriptError::Failed(e));
+		}
 
-	trace!(
-		"Running: python3 src/modules/odoo/python/odoo_get.py {} {} {} {} {}",
-		&c.odoo_url,
-		&c.odoo_db,
-		&c.odoo_uid,
-		&c.odoo_pw,
-		&last_run
-	);
+		return Err(ScriptError::Exit {
+			code: output.status.code(),
+			stderr,
+		});
+	}
 
-	let a = Command::new("python3")
-		.args(&[
-			"src/modules/odoo/python/odoo_get.py",
-			&c.odoo_url,
-			&c.odoo_db,
-			&c.odoo_uid,
-			&c.odoo_pw,
-			&last_run,
-		])
-		.output();
-
-	let a = match a {
-		Ok(x) => x,
+	let stdout = match str::from_utf8(&output.stdout) {
+		Ok(v) => v,
 		Err(e) => {
-			let text = format!("Python3 failed: {}", e);
-
-			let _ = write_odoo_call_log(
-				&conn,
-				"src/modules/odoo/python/odoo_get.py",
-				Some(&c.odoo_url),
-				Some(&c.odoo_db),
-				Some(&c.odoo_uid),
-				Some(&c.odoo_pw),
-				Some(&last_run),
-				None,
-				false,
-				Some(&text),
-			)
-			.await;
-
-			error!("{}", text);
-
-			return Err(text);
+			return Err(ScriptError::InvalidOutput {
+				reason: format!("Invalid UTF-8 sequence on stdout: {}", e),
+				stderr,
+			})
 		}
 	};
 
-	let s = match str::from_utf8(&a.stdout) {
+	trace!("Output:\n{}", stdout);
+
+	let envelope: ScriptEnvelope = match serde_json::from_str(stdout) {
 		Ok(v) => v,
 		Err(e) => {
-			let text = format!("Invalid UTF-8 sequence on stdout: {}", e);
-			error!("{}", text);
-			return Err(text);
+			return Err(ScriptError::InvalidOutput {
+				reason: format!("JSON was not well-formatted: {}", e),
+				stderr,
+			})
 		}
 	};
 
-	trace!("Output:\n{}", &s);
+	if !envelope.ok {
+		return Err(ScriptError::Failed(
+			envelope
+				.error
+				.unwrap_or_else(|| format!("{} failed", script_path)),
+		));
+	}
 
-	let er = match str::from_utf8(&a.stderr) {
+	if !stderr.is_empty() {
+		warn!("{} succeeded but wrote to stderr:\n{}", script_path, stderr);
+	}
+
+	Ok(envelope.data)
+}
+
+/// Runs `python3 script_path`, killing it if it is still running after
+/// `script_timeout` seconds.
+async fn run_python(
+	c: &OdooConfig,
+	script_path: &str,
+	payload: &str,
+) -> Result<Output, ScriptError> {
+	let child = Command::new("python3")
+		.arg(script_path)
+		.env("ODOO_URL", &c.odoo_url)
+		.env("ODOO_DB", &c.odoo_db)
+		.env("ODOO_USERNAME", &c.odoo_uid)
+		.env("ODOO_PASSWORD", &c.odoo_pw)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.kill_on_drop(true)
+		.spawn();
+
+	let mut child = match child {
 		Ok(v) => v,
-		Err(e) => {
-			let text = format!("Invalid UTF-8 sequence on stderr: {}", e);
-			error!("{}", text);
-			return Err(text);
+		Err(e) => return Err(ScriptError::Spawn(e.to_string())),
+	};
+
+	let run = async {
+		if let Some(mut stdin) = child.stdin.take() {
+			stdin.write_all(payload.as_bytes()).await?;
 		}
+
+		child.wait_with_output().await
 	};
 
-	if er.is_empty() == false {
-		trace!("Errors:\n{}", &er);
+	match tokio::time::timeout(Duration::from_secs(c.script_timeout), run).await {
+		Ok(Ok(output)) => Ok(output),
+		Ok(Err(e)) => Err(ScriptError::Spawn(e.to_string())),
+		Err(_) => Err(ScriptError::Timeout(c.script_timeout)),
 	}
+}
 
-	let json = //: Vec<OdooProjectHeader> =
-		serde_json::from_str(s); //.expect("JSON was not well-formatted");
+async fn get_odoo_deals(
+	pool: &Pool,
+	last_run: Option<i64>,
+	ids: &[i32],
+) -> Result<Vec<OdooProjectHeader>, String> {
+	let c = get_config()?;
 
-	let json = match json {
-		Ok(v) => v,
-		Err(e) => {
-			let _ = write_odoo_call_log(
-				&conn,
-				"src/modules/odoo/python/odoo_get.py",
-				Some(&c.odoo_url),
-				Some(&c.odoo_db),
-				Some(&c.odoo_uid),
-				Some(&c.odoo_pw),
-				Some(&last_run),
-				None,
-				false,
-				Some(&format!("JSON was not well-formatted: {}", e)),
-			)
-			.await;
+	let result = fetch_odoo_deals(&c, last_run, ids).await;
 
-			return Err(format!("JSON was not well-formatted: {}", e));
-		}
+	let (ok, response) = match &result {
+		Ok((_, output)) => (true, output.clone()),
+		Err(text) => (false, text.clone()),
+	};
+
+	let last_run: String = match last_run {
+		Some(x) => x.to_string(),
+		None => "".to_string(),
 	};
 
 	let _ = write_odoo_call_log(
-		&conn,
+		pool,
 		"src/modules/odoo/python/odoo_get.py",
 		Some(&c.odoo_url),
 		Some(&c.odoo_db),
 		Some(&c.odoo_uid),
-		Some(&c.odoo_pw),
+		None,
 		Some(&last_run),
 		None,
-		true,
-		Some(&s),
+		ok,
+		Some(&response),
 	)
 	.await;
 
-	Ok(json)
+	result.map(|(deals, _)| deals)
 }
 
-async fn put_odoo_matches(conn: &PgConnection, matches: Vec<ProjectMatch>) -> Result<(), String> {
-	let c = get_config();
+/// Runs `odoo_get.py` and parses its output. Returns the projects together
+/// with the raw output. Nothing is written to `odoo_call_log`. With a
+/// `last_run` the leads in `ids` are returned too, changed or not.
+async fn fetch_odoo_deals(
+	c: &OdooConfig,
+	last_run: Option<i64>,
+	ids: &[i32],
+) -> Result<(Vec<OdooProjectHeader>, String), String> {
+	let payload = serde_json::json!({
+		"last_run": last_run,
+		"ids": ids,
+		"percentage_field": c.percentage_field,
+		"max_years_field": c.max_years_field,
+	})
+	.to_string();
+
+	let data = call_odoo_script(c, "src/modules/odoo/python/odoo_get.py", &payload).await;
+
+	let data = match data {
+		Ok(v) => v,
+		Err(e) => {
+			error!("{}", e);
+			return Err(e.into());
+		}
+	};
+
+	let raw = data.to_string();
+
+	let mut deals: Vec<OdooProjectHeader> = match serde_json::from_value(data) {
+		Ok(v) => v,
+		Err(e) => return Err(format!("JSON was not well-formatted: {}", e)),
+	};
 
-	let odoo_matches = serde_json::to_string(&matches);
+	for need in deals.iter_mut().flat_map(|x| x.needs.iter_mut()) {
+		need.percentage = need.percentage.or(c.default_percentage);
+
+		for skill in &mut need.skills {
+			skill.max_years = skill.max_years.or(c.default_max_years);
+		}
+	}
+
+	Ok((deals, raw))
+}
+
+async fn put_odoo_matches(
+	pool: &Pool,
+	matches: &[ProjectMatch],
+	invalid: &[InvalidLead],
+) -> Result<(), String> {
+	let c = get_config()?;
+
+	let summary_field = match &c.match_summary {
+		SummaryTarget::Field(field) => Some(field),
+		_ => None,
+	};
+
+	let odoo_matches = serde_json::to_string(&serde_json::json!({
+		"link_text": c.link_text,
+		"link_texts": c.link_texts,
+		"summary_field": summary_field,
+		"summary_note": c.match_summary == SummaryTarget::Note,
+		"error_field": c.error_field,
+		"matches": matches,
+		"errors": invalid,
+	}));
 
 	let odoo_matches = match odoo_matches {
-		Ok(v) => format!("{}", v),
+		Ok(v) => v,
 		Err(e) => return Err(format!("Serde failed: {}", e)),
 	};
 
-	trace!(
-		"Running: python3 src/modules/odoo/python/odoo_put.py {} {} {} {} {}",
-		&c.odoo_url,
-		&c.odoo_db,
-		&c.odoo_uid,
-		&c.odoo_pw,
-		&odoo_matches
-	);
+	run_odoo_script(
+		String::from("src/modules/odoo/python/odoo_put.py"),
+		pool,
+		&odoo_matches,
+	)
+	.await?;
 
-	let cmd = Command::new("python3")
-		.args(&[
-			"src/modules/odoo/python/odoo_put.py",
-			&c.odoo_url,
-			&c.odoo_db,
-			&c.odoo_uid,
-			&c.odoo_pw,
-			&odoo_matches,
-		])
-		.output();
-
-	let _ = match cmd {
-		Ok(x) => x,
-		Err(e) => {
-			let _ = write_odoo_call_log(
-				&conn,
-				"src/modules/odoo/python/odoo_put.py",
-				Some(&c.odoo_url),
-				Some(&c.odoo_db),
-				Some(&c.odoo_uid),
-				Some(&c.odoo_pw),
-				Some(&odoo_matches),
-				None,
-				false,
-				Some(&format!("Python3 failed: {}", e)),
+	Ok(())
+}
+
+async fn get_odoo_log(pool: &Pool) -> Result<Vec<OdooProject>, String> {
+	use crate::schema::odoo_projects::dsl::odoo_projects;
+	let items = db::run(pool, |conn| odoo_projects.load::<OdooProject>(conn)).await?;
+
+	let items = match items {
+		Ok(items) => items,
+		Err(e) => return Err(format!("get_odoo_log failed: {}", e)),
+	};
+
+	Ok(items)
+}
+
+impl CrmRecord for OdooProjectHeader {
+	type Key = i32;
+
+	fn crm_key(&self) -> i32 {
+		self.id
+	}
+}
+
+impl Mapping for OdooProject {
+	type Key = i32;
+
+	fn crm_key(&self) -> i32 {
+		self.odoo_id
+	}
+
+	fn hula_id(&self) -> Option<uuid::Uuid> {
+		self.hula_id
+	}
+
+	fn sync_status(&self) -> &str {
+		&self.sync_status
+	}
+}
+
+/// The lead's `ProjectMatch` and any Hula-side edits to write back to it.
+type JobResult = Result<(ProjectMatch, Option<LeadUpdate>), JobError>;
+
+/// Why a job failed.
+struct JobError {
+	error: String,
+	/// Hula rejected the request, see `SyncError::is_permanent`.
+	permanent: bool,
+}
+
+impl JobError {
+	/// A failed Hula call, named by `call`.
+	fn hula(call: &str, e: SyncError) -> JobError {
+		JobError {
+			permanent: e.is_permanent(),
+			error: format!("{} failed: {}", call, e),
+		}
+	}
+}
+
+impl From<String> for JobError {
+	fn from(error: String) -> JobError {
+		JobError {
+			error,
+			permanent: false,
+		}
+	}
+}
+
+/// One Hula call of a sync run, see `do_process_internal`.
+enum Job<'a> {
+	Update(&'a Mapped<'a, OdooProjectHeader, OdooProject>),
+	Refresh(&'a Mapped<'a, OdooProjectHeader, OdooProject>),
+	Create(&'a Create<'a, OdooProjectHeader, OdooProject>),
+}
+
+impl Job<'_> {
+	fn crm_id(&self) -> i32 {
+		match self {
+			Job::Update(change) | Job::Refresh(change) => change.record.id,
+			Job::Create(change) => change.record.id,
+		}
+	}
+
+	/// The lead whose structure the job sends to Hula.
+	fn outgoing(&self) -> Option<&OdooProjectHeader> {
+		match self {
+			Job::Update(change) => Some(change.record),
+			Job::Create(change) => Some(change.record),
+			Job::Refresh(_) => None,
+		}
+	}
+
+	/// What a successful run of the job did to the record, for metrics.
+	fn action(&self) -> &'static str {
+		match self {
+			Job::Update(_) => "updated",
+			Job::Create(_) => "created",
+			Job::Refresh(_) => "skipped",
+		}
+	}
+}
+
+/// A lead skipped because its structure failed validation.
+#[derive(Serialize, Debug)]
+pub struct InvalidLead {
+	pub id: i32,
+	pub error: String,
+}
+
+/// Whether the Hula project was edited after its mapping was last synced.
+fn hula_changed(row: &OdooProject, project: &HulaProject) -> bool {
+	match (row.synced_at, project.updated_at()) {
+		(Some(synced), Some(updated)) => updated > synced,
+		_ => false,
+	}
+}
+
+/// Leads whose Hula project was edited after their mapping was synced.
+fn hula_changed_leads(log: &[OdooProject], projects: &[HulaProject]) -> Vec<i32> {
+	let index = ProjectIndex::new(projects);
+
+	log.iter()
+		.filter(|x| x.sync_status == sync_status::SYNCED)
+		.filter(|row| match row.hula_id.and_then(|id| index.get(&id)) {
+			Some(project) => hula_changed(row, project),
+			None => false,
+		})
+		.map(|row| row.odoo_id)
+		.collect()
+}
+
+/// What the jobs of a sync run need to know besides the records.
+struct RunState {
+	reverse: ReverseConfig,
+	/// Unapplied conflicts by mapping id.
+	conflicts: HashMap<uuid::Uuid, Vec<SyncConflict>>,
+	/// Hula's skills and skill scope levels, see `validation`.
+	skills: Vec<Skill>,
+	levels: Vec<SkillScopeLevel>,
+	/// Leads that failed recently and are not yet due for a retry, with the
+	/// structure that failed. A lead edited since is tried right away.
+	waiting: HashMap<i32, Option<String>>,
+	/// Leads in the dead-letter queue, not synced until retried from the CLI.
+	dead: HashSet<i32>,
+	/// Edited structures of dead letters queued for a retry.
+	overrides: HashMap<i32, HulaProjectStructureData>,
+}
+
+impl RunState {
+	/// The structure sent to Hula for `deal`.
+	fn outgoing(&self, deal: &OdooProjectHeader) -> HulaProjectStructureData {
+		match self.overrides.get(&deal.id) {
+			Some(data) => data.clone(),
+			None => deal.into(),
+		}
+	}
+}
+
+/// A lead that could not be synced.
+struct Failure {
+	id: i32,
+	/// The structure that was to be sent, as JSON.
+	payload: Option<String>,
+	error: String,
+	/// The lead failed validation or was rejected, so retrying it unchanged
+	/// fails the same way. Other failures are retried indefinitely.
+	permanent: bool,
+}
+
+/// Schedules a retry of a failed lead, or moves it to the dead-letter
+/// queue once it has been rejected `dead_letter::max_attempts` times.
+/// Failures that may pass on their own, like Hula being unreachable, are
+/// retried with backoff but never move a lead to the queue.
+/// `requeued` holds the attempts of a dead letter retried from the CLI,
+/// which goes straight back to the queue if it is rejected again.
+async fn record_failure(pool: &Pool, failure: Failure, requeued: Option<i32>) {
+	let attempts = match requeued {
+		Some(attempts) if failure.permanent => attempts + 1,
+		_ => {
+			let retry = retries::record_failure(
+				pool,
+				"odoo",
+				failure.id.to_string(),
+				failure.payload.clone(),
+				failure.error.clone(),
+				failure.permanent,
 			)
 			.await;
 
-			return Err(format!("Python3 failed: {}", e));
+			if requeued.is_some() && retry.is_ok() {
+				// The lead is retried with backoff like any other from now on.
+				let cleared = dead_letter::clear(pool, "odoo", vec![failure.id.to_string()]).await;
+				if let Err(e) = cleared {
+					error!("Clearing dead letter of lead {} failed: {}", failure.id, e);
+				}
+			}
+
+			match retry {
+				Ok(retry)
+					if !failure.permanent || retry.rejections < dead_letter::max_attempts() =>
+				{
+					warn!(
+						"Lead {} failed {} time(s), retrying after {}.",
+						failure.id, retry.attempts, retry.next_attempt_at
+					);
+					return;
+				}
+				Ok(retry) => retry.attempts,
+				Err(e) => {
+					error!("Recording retry of lead {} failed: {}", failure.id, e);
+					return;
+				}
+			}
 		}
 	};
 
-	let _ = write_odoo_call_log(
-		&conn,
-		"src/modules/odoo/python/odoo_put.py",
-		Some(&c.odoo_url),
-		Some(&c.odoo_db),
-		Some(&c.odoo_uid),
-		Some(&c.odoo_pw),
-		Some(&odoo_matches),
-		None,
-		true,
-		None,
+	let buried = dead_letter::bury(
+		pool,
+		"odoo",
+		failure.id.to_string(),
+		failure.payload.unwrap_or_default(),
+		failure.error,
+		attempts,
 	)
 	.await;
 
-	Ok(())
+	match buried {
+		Ok(_) => error!(
+			"Lead {} failed {} times, moved to the dead-letter queue.",
+			failure.id, attempts
+		),
+		Err(e) => error!(
+			"Moving lead {} to the dead-letter queue failed: {}",
+			failure.id, e
+		),
+	}
+}
+
+/// What a sync run did, record by record.
+struct RunOutcome {
+	matches: Vec<ProjectMatch>,
+	leads: Vec<LeadUpdate>,
+	/// Leads skipped because their structure is invalid.
+	invalid: Vec<InvalidLead>,
+	/// Leads that were invalid or whose Hula calls failed.
+	failed: Vec<Failure>,
+}
+
+async fn do_process_internal(
+	config: &HulaConfig,
+	pool: &Pool,
+	state: &RunState,
+	deals: Vec<OdooProjectHeader>,
+	projects: Vec<HulaProject>,
+	log: Vec<OdooProject>,
+) -> Result<RunOutcome, String> {
+	let hashes: HashMap<i32, String> = deals
+		.iter()
+		.map(|x| (x.id, content_hash(&x.into())))
+		.collect();
+
+	let changes = changeset::diff(&deals, &log, &projects, |deal, row, project| {
+		row.content_hash.as_ref() != hashes.get(&deal.id)
+			|| (state.reverse.enabled && hula_changed(row, project))
+			|| state
+				.conflicts
+				.get(&row.id)
+				.is_some_and(|x| x.iter().any(|c| c.status != sync_conflict::OPEN))
+	});
+
+	for row in &changes.orphaned {
+		trace!("Skipping lead {}, Hula project missing.", row.odoo_id);
+	}
+
+	let mut jobs: Vec<Job> = vec![];
+	let mut invalid: Vec<InvalidLead> = vec![];
+	let mut failed: Vec<Failure> = vec![];
+	let mut payloads: HashMap<i32, Option<String>> = HashMap::new();
+
+	for job in changes
+		.update
+		.iter()
+		.map(Job::Update)
+		.chain(changes.unchanged.iter().map(Job::Refresh))
+		.chain(changes.create.iter().map(Job::Create))
+	{
+		let id = job.crm_id();
+
+		if state.dead.contains(&id) {
+			trace!("Skipping lead {}, it is in the dead-letter queue.", id);
+			metrics::records("odoo", "skipped", 1);
+			continue;
+		}
+
+		let outgoing = job.outgoing().map(|deal| state.outgoing(deal));
+		let payload = outgoing
+			.as_ref()
+			.and_then(|data| serde_json::to_string(data).ok());
+
+		if let Some(failed) = state.waiting.get(&id) {
+			if outgoing.is_none() || failed == &payload {
+				trace!("Skipping lead {}, waiting for its retry.", id);
+				metrics::records("odoo", "skipped", 1);
+				continue;
+			}
+		}
+
+		if let Some(data) = &outgoing {
+			let problems = validation::validate(data, &state.skills, &state.levels);
+
+			if !problems.is_empty() {
+				let text: Vec<String> = problems.iter().map(|x| x.to_string()).collect();
+				let text = text.join("; ");
+				error!("Skipping lead {}: {}", id, &text);
+				invalid.push(InvalidLead {
+					id,
+					error: text.clone(),
+				});
+				failed.push(Failure {
+					id,
+					payload,
+					error: text,
+					permanent: true,
+				});
+				continue;
+			}
+		}
+
+		payloads.insert(id, payload);
+		jobs.push(job);
+	}
+
+	let results: Vec<(i32, &str, JobResult)> = stream::iter(jobs)
+		.map(|job| async {
+			let id = job.crm_id();
+			let action = job.action();
+			(id, action, run_job(config, pool, state, job, &hashes).await)
+		})
+		.buffer_unordered(config.concurrency)
+		.collect()
+		.await;
+
+	let mut matches: Vec<ProjectMatch> = vec![];
+	let mut leads: Vec<LeadUpdate> = vec![];
+
+	for (id, action, result) in results {
+		match result {
+			Ok((item, lead)) => {
+				metrics::records("odoo", action, 1);
+				matches.push(item);
+				leads.extend(lead);
+			}
+			Err(e) => {
+				error!("Lead {}: {}", id, &e.error);
+				failed.push(Failure {
+					id,
+					payload: payloads.remove(&id).flatten(),
+					error: e.error,
+					permanent: e.permanent,
+				});
+			}
+		}
+	}
+
+	if get_config()?.match_summary != SummaryTarget::Off {
+		attach_summaries(config, pool, &log, &mut matches).await;
+	}
+
+	Ok(RunOutcome {
+		matches,
+		leads,
+		invalid,
+		failed,
+	})
+}
+
+/// Fetches the matched candidates of each project and sets `summary` where
+/// it differs from the one last written to the lead. A failed fetch leaves
+/// the summary out, it is tried again on the next run.
+async fn attach_summaries(
+	config: &HulaConfig,
+	pool: &Pool,
+	log: &[OdooProject],
+	matches: &mut [ProjectMatch],
+) {
+	let written: HashMap<uuid::Uuid, &Option<String>> =
+		log.iter().map(|x| (x.id, &x.summary_hash)).collect();
+
+	stream::iter(matches.iter_mut())
+		.for_each_concurrent(config.concurrency, |item| {
+			let previous = written.get(&item.mapping_id).copied();
+
+			async move {
+				let candidates = match get_hula_matches_odoo(pool, config, item).await {
+					Ok(v) => v,
+					Err(e) => {
+						error!("get_hula_matches_odoo failed for lead {}: {}", item.id, e);
+						return;
+					}
+				};
+
+				let summary = match_summary(&candidates);
+				let hash = format!("{:x}", Sha256::digest(summary.as_bytes()));
+
+				if previous.and_then(|x| x.as_ref()) == Some(&hash) {
+					return;
+				}
+
+				item.summary = Some(summary);
+				item.summary_hash = Some(hash);
+			}
+		})
+		.await;
+}
+
+/// One line per candidate, best match first.
+fn match_summary(candidates: &[HulaMatch]) -> String {
+	if candidates.is_empty() {
+		return "No matching candidates.".to_string();
+	}
+
+	let mut sorted: Vec<&HulaMatch> = candidates.iter().collect();
+	sorted.sort_by(|a, b| {
+		b.score
+			.unwrap_or_default()
+			.partial_cmp(&a.score.unwrap_or_default())
+			.unwrap_or(std::cmp::Ordering::Equal)
+	});
+
+	sorted
+		.iter()
+		.map(|x| {
+			let mut line = x.name.clone();
+			if let Some(score) = x.score {
+				line.push_str(&format!(", {:.0} %", score));
+			}
+			if let Some(availability) = &x.availability {
+				line.push_str(&format!(", {}", availability));
+			}
+			line
+		})
+		.collect::<Vec<String>>()
+		.join("\n")
 }
 
-async fn get_odoo_log(conn: &PgConnection) -> Result<Vec<OdooProject>, String> {
-	use crate::schema::odoo_projects::dsl::odoo_projects;
-	let items = odoo_projects.load::<OdooProject>(conn);
+/// Remembers the summaries written to Odoo so unchanged ones are not
+/// written again.
+async fn store_summary_hashes(pool: &Pool, matches: &[ProjectMatch]) {
+	use crate::schema::odoo_projects::dsl;
 
-	let items = match items {
-		Ok(items) => items,
-		Err(e) => return Err(format!("get_odoo_log failed: {}", e)),
-	};
+	for item in matches.iter().filter(|x| x.summary.is_some()) {
+		let id = item.mapping_id;
+		let hash = item.summary_hash.clone();
+
+		let updated = db::run(pool, move |conn| {
+			diesel::update(dsl::odoo_projects.find(id))
+				.set(dsl::summary_hash.eq(hash))
+				.execute(conn)
+		})
+		.await;
 
-	return Ok(items);
+		match updated {
+			Ok(Ok(_)) => {}
+			Ok(Err(e)) => error!("Storing summary hash failed: {}", e),
+			Err(e) => error!("Storing summary hash failed: {}", e),
+		}
+	}
 }
 
-async fn do_process_internal(
+async fn run_job(
 	config: &HulaConfig,
-	conn: &PgConnection,
-	deals: Vec<OdooProjectHeader>,
-	projects: Vec<HulaProject>,
-	log: Vec<OdooProject>,
-) -> Result<Vec<ProjectMatch>, String> {
-	let mut matches: Vec<ProjectMatch> = vec![];
-
-	/* iterate log, see what needs update */
-	for log1 in &log {
-		let h = projects.iter();
-		let a = h.filter(|x| x.id == log1.hula_id.to_string()).next();
+	pool: &Pool,
+	state: &RunState,
+	job: Job<'_>,
+	hashes: &HashMap<i32, String>,
+) -> JobResult {
+	match job {
+		/* update projects of already mapped leads */
+		Job::Update(change) => {
+			let known = state
+				.conflicts
+				.get(&change.mapping.id)
+				.map_or(&[][..], |x| &x[..]);
+			let crm = state.outgoing(change.record);
+			run_update(config, pool, &state.reverse, known, change, crm, hashes).await
+		}
 
-		if let Some(b) = a {
-			let h2 = deals.iter();
-			let a2 = h2.filter(|x| x.id == log1.odoo_id).next();
+		/* projects sent unchanged last time only need a fresh match count */
+		Job::Refresh(change) => {
+			let current = get_hula_project_odoo::<HulaProjectStructureResponse>(
+				pool,
+				config,
+				change.project.id.clone(),
+				change.record,
+			)
+			.await;
+			let current = match current {
+				Ok(item) => item,
+				Err(e) => return Err(JobError::hula("get_hula_project_odoo", e)),
+			};
 
-			if let Some(b2) = a2 {
-				let updated = update_hula_project_odoo(conn, config, b.id.clone(), b2).await;
-				let updated = match updated {
-					Ok(item) => item,
-					Err(e) => return Err(format!("update_hula_project_odoo failed: {}", e)),
-				};
-				matches.push(ProjectMatch {
-					id: log1.odoo_id,
-					matches: updated.matches,
-					link: format!("{}/app/project/{}", &config.hula_url, log1.hula_id),
-				});
-			}
+			Ok((
+				ProjectMatch {
+					id: change.mapping.odoo_id,
+					matches: current.matches,
+					link: hulautils::project_link(config, &change.hula_id),
+					summary: None,
+					mapping_id: change.mapping.id,
+					hula_id: change.hula_id,
+					summary_hash: None,
+				},
+				None,
+			))
 		}
-	}
 
-	/* iterate deals, see what needs insert */
-	for deal in &deals {
-		let mut h = log.iter();
-		if h.any(|x| x.odoo_id == deal.id) == false {
-			let added = insert_hula_project_odoo(conn, config, deal).await;
+		/* create projects for new leads */
+		Job::Create(change) => {
+			let deal = change.record;
+
+			let reserved_id = match change.pending {
+				Some(row) => row.id,
+				None => {
+					let reserved = insert_odoo_log(pool, deal.id, deal.name.clone()).await;
+					match reserved {
+						Ok(item) => item,
+						Err(e) => return Err(format!("insert_odoo_log failed: {}", e).into()),
+					}
+				}
+			};
+
+			let data = state.outgoing(deal);
+			let added =
+				insert_hula_project_odoo(pool, config, deal, data.clone(), reserved_id).await;
 			let added = match added {
 				Ok(item) => item,
-				Err(e) => return Err(format!("insert_hula_project_odoo failed: {}", e)),
+				Err(e) => return Err(JobError::hula("insert_hula_project_odoo", e)),
 			};
 
 			let my_uuid = added.id;
 
-			let inserted = insert_odoo_log(&conn, my_uuid, deal.id, deal.name.clone()).await;
-			let _ = match inserted {
-				Ok(item) => item,
-				Err(e) => return Err(format!("insert_odoo_log failed: {}", e)),
-			};
+			let confirmed =
+				update_odoo_log(pool, reserved_id, Some(my_uuid), sync_status::SYNCED).await;
+			if let Err(e) = confirmed {
+				return Err(format!("update_odoo_log failed: {}", e).into());
+			}
 
-			matches.push(ProjectMatch {
-				id: deal.id,
-				matches: added.matches,
-				link: format!("{}/app/project/{}", &config.hula_url, &my_uuid),
-			});
+			let stored =
+				update_odoo_log_hash(pool, reserved_id, hashes.get(&deal.id), snapshot(&data))
+					.await;
+			if let Err(e) = stored {
+				error!("update_odoo_log_hash failed: {}", e);
+			}
+
+			Ok((
+				ProjectMatch {
+					id: deal.id,
+					matches: added.matches,
+					link: hulautils::project_link(config, &my_uuid),
+					summary: None,
+					mapping_id: reserved_id,
+					hula_id: my_uuid,
+					summary_hash: None,
+				},
+				None,
+			))
 		}
 	}
-
-	Ok(matches)
 }
 
-async fn insert_odoo_log(
-	conn: &PgConnection,
-	hula_id: uuid::Uuid,
-	odoo_id: i32,
-	name: String,
-) -> Result<(), String> {
+/// Reserves a pending mapping row for a lead before its Hula project is
+/// created. The returned row id doubles as the project's external id.
+async fn insert_odoo_log(pool: &Pool, odoo_id: i32, name: String) -> Result<uuid::Uuid, String> {
 	use crate::schema::odoo_projects::dsl::odoo_projects;
 
 	let new_project = OdooProject {
 		id: uuid::Uuid::new_v4(),
-		hula_id: hula_id,
-		odoo_id: odoo_id,
+		hula_id: None,
+		odoo_id,
 		name: name.clone(),
 		updated_by: "hulasync".to_string(),
+		sync_status: sync_status::PENDING.to_string(),
+		content_hash: None,
+		summary_hash: None,
+		synced_at: None,
+		snapshot: None,
 	};
 
-	let rows_inserted = diesel::insert_into(odoo_projects)
-		.values(&new_project)
-		.get_result::<OdooProject>(conn);
+	let rows_inserted = db::run(pool, move |conn| {
+		diesel::insert_into(odoo_projects)
+			.values(&new_project)
+			.get_result::<OdooProject>(conn)
+	})
+	.await?;
+
+	match rows_inserted {
+		Ok(row) => Ok(row.id),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+async fn update_odoo_log(
+	pool: &Pool,
+	id: uuid::Uuid,
+	hula_id: Option<uuid::Uuid>,
+	status: &str,
+) -> Result<(), String> {
+	use crate::schema::odoo_projects::dsl;
+
+	let status = status.to_string();
+	let updated = db::run(pool, move |conn| {
+		diesel::update(dsl::odoo_projects.find(id))
+			.set((dsl::hula_id.eq(hula_id), dsl::sync_status.eq(status)))
+			.execute(conn)
+	})
+	.await?;
+
+	match updated {
+		Ok(_) => Ok(()),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+/// The project structure as stored in `odoo_projects.snapshot`.
+fn snapshot(data: &HulaProjectStructureData) -> Option<String> {
+	serde_json::to_string(data).ok()
+}
+
+/// Remembers the hash and snapshot of the project structure last synced
+/// with Hula and when that happened.
+async fn update_odoo_log_hash(
+	pool: &Pool,
+	id: uuid::Uuid,
+	hash: Option<&String>,
+	snapshot: Option<String>,
+) -> Result<(), String> {
+	use crate::schema::odoo_projects::dsl;
+
+	let hash = hash.cloned();
+	let updated = db::run(pool, move |conn| {
+		diesel::update(dsl::odoo_projects.find(id))
+			.set((
+				dsl::content_hash.eq(hash),
+				dsl::synced_at.eq(chrono::Utc::now().naive_utc()),
+				dsl::snapshot.eq(snapshot),
+			))
+			.execute(conn)
+	})
+	.await?;
+
+	match updated {
+		Ok(_) => Ok(()),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+async fn delete_odoo_log(pool: &Pool, id: uuid::Uuid) -> Result<(), String> {
+	use crate::schema::odoo_projects::dsl::odoo_projects;
+
+	let deleted = db::run(pool, move |conn| {
+		diesel::delete(odoo_projects.find(id)).execute(conn)
+	})
+	.await?;
+
+	match deleted {
+		Ok(_) => Ok(()),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+/// Sets mapping rows marked stale by `reconcile` back to synced when their
+/// lead is returned again. A lead that regains the fields needed for
+/// syncing has a new write date, so incremental runs pick it up.
+async fn restore_stale_odoo_log(
+	pool: &Pool,
+	deals: &[OdooProjectHeader],
+	mut log: Vec<OdooProject>,
+) -> Result<Vec<OdooProject>, String> {
+	let returned: HashSet<i32> = deals.iter().map(|x| x.id).collect();
+
+	for row in log
+		.iter_mut()
+		.filter(|x| x.sync_status == sync_status::STALE)
+		.filter(|x| returned.contains(&x.odoo_id))
+	{
+		update_odoo_log(pool, row.id, row.hula_id, sync_status::SYNCED).await?;
+		trace!("Restored stale mapping for Odoo lead {}.", row.odoo_id);
+
+		row.sync_status = sync_status::SYNCED.to_string();
+	}
+
+	Ok(log)
+}
+
+/// Completes mapping rows left pending by an interrupted run, using the
+/// external id the Hula project was created with. Rows without a matching
+/// project stay pending and the lead is created again.
+async fn reconcile_pending_odoo_log(
+	pool: &Pool,
+	projects: &[HulaProject],
+	mut log: Vec<OdooProject>,
+) -> Result<Vec<OdooProject>, String> {
+	for row in log
+		.iter_mut()
+		.filter(|x| x.sync_status == sync_status::PENDING)
+	{
+		let project = match find_by_external_id(projects, &row.id) {
+			Some(project) => project,
+			None => continue,
+		};
+
+		let hula_id = match uuid::Uuid::parse_str(&project.id) {
+			Ok(id) => id,
+			Err(e) => {
+				error!("Invalid Hula project id {}: {}", &project.id, e);
+				continue;
+			}
+		};
+
+		update_odoo_log(pool, row.id, Some(hula_id), sync_status::SYNCED).await?;
+		trace!("Recovered mapping for Odoo lead {}.", row.odoo_id);
+
+		row.hula_id = Some(hula_id);
+		row.sync_status = sync_status::SYNCED.to_string();
+	}
+
+	Ok(log)
+}
+
+/// Applies the orphan policy to mapping rows whose Hula project has been
+/// deleted. Returns the remaining rows and the policy applied, `Ignore` if
+/// no row was orphaned or `orphans::guard` held the policy back.
+async fn handle_orphaned_odoo_log(
+	pool: &Pool,
+	projects: &[HulaProject],
+	log: Vec<OdooProject>,
+	policy: OrphanPolicy,
+) -> Result<(Vec<OdooProject>, OrphanPolicy), String> {
+	let index = ProjectIndex::new(projects);
+	let orphaned: HashSet<uuid::Uuid> = changeset::orphaned(&log, &index)
+		.iter()
+		.map(|x| x.id)
+		.collect();
+
+	let synced = log
+		.iter()
+		.filter(|x| x.sync_status == sync_status::SYNCED)
+		.count();
+	let policy = orphans::guard(policy, "Odoo", projects.len(), synced, orphaned.len());
+
+	let mut remaining = Vec::with_capacity(log.len());
+
+	for mut row in log {
+		if !orphaned.contains(&row.id) {
+			remaining.push(row);
+			continue;
+		}
 
-	if rows_inserted.is_ok() {
-		return Ok(());
+		let hula_id = row.hula_id.map(|x| x.to_string()).unwrap_or_default();
+
+		warn!(
+			"Hula project {} for Odoo lead {} no longer exists, policy {:?}.",
+			&hula_id, row.odoo_id, policy
+		);
+
+		match policy {
+			OrphanPolicy::Ignore => remaining.push(row),
+			OrphanPolicy::Recreate => {
+				update_odoo_log(pool, row.id, None, sync_status::PENDING).await?;
+				row.hula_id = None;
+				row.sync_status = sync_status::PENDING.to_string();
+				remaining.push(row);
+			}
+			OrphanPolicy::Reimport => {
+				delete_odoo_log(pool, row.id).await?;
+			}
+			OrphanPolicy::Remove => {
+				update_odoo_log(pool, row.id, row.hula_id, sync_status::REMOVED).await?;
+				row.sync_status = sync_status::REMOVED.to_string();
+				remaining.push(row);
+			}
+		}
 	}
 
-	return Err("failed".to_string());
+	let applied = if orphaned.is_empty() {
+		OrphanPolicy::Ignore
+	} else {
+		policy
+	};
+
+	Ok((remaining, applied))
 }
 
 pub async fn insert_hula_project_odoo(
-	conn: &PgConnection,
+	pool: &Pool,
 	config: &HulaConfig,
 	header: &OdooProjectHeader,
-) -> Result<HulaProjectStructureResponse, &'static str> {
+	mut data: HulaProjectStructureData,
+	external_id: uuid::Uuid,
+) -> Result<HulaProjectStructureResponse, SyncError> {
 	let request_url = format!("{}/api/projectstructures", config.hula_url);
 
 	let client = reqwest::Client::new();
 
-	let data: HulaProjectStructureData = header.into();
+	data.external_id = Some(external_id.to_string());
 
-	let response = client
-		.post(&request_url)
-		.header("Cookie", format!("auth={}", config.cookie))
-		.json(&data)
-		.send()
-		.await;
+	let response = hulautils::send(
+		config,
+		client
+			.post(&request_url)
+			.header("Cookie", format!("auth={}", config.cookie))
+			.json(&data),
+	)
+	.await;
 
 	let response = match response {
 		Ok(file) => {
-			if file.status().as_u16() > 299 {
+			let status = file.status();
+
+			if !status.is_success() {
 				let _ = write_hula_log(
-					conn,
+					pool,
 					None,
 					header.id,
 					&request_url,
 					"POST",
 					&format!("{:?}", &data),
-					file.status().as_u16().into(),
-					&format!("{}", &file.text().await.unwrap()),
+					status.as_u16().into(),
+					&file.text().await.unwrap_or_default(),
 				)
 				.await;
 
-				return Err("11");
+				return Err(SyncError::Status(status));
 			}
 			file
 		}
 		Err(e) => {
 			let _ = write_hula_log(
-				conn,
+				pool,
 				None,
 				header.id,
 				&request_url,
@@ -685,7 +1928,7 @@ pub async fn insert_hula_project_odoo(
 			)
 			.await;
 
-			return Err("1");
+			return Err(SyncError::Http(e));
 		}
 	};
 
@@ -697,25 +1940,25 @@ pub async fn insert_hula_project_odoo(
 		Ok(file) => file,
 		Err(e) => {
 			let _ = write_hula_log(
-				conn,
+				pool,
 				None,
 				header.id,
 				&request_url,
 				"POST",
 				&format!("{:?}", &data),
-				e.status().unwrap().as_u16().into(),
+				status,
 				&format!("{}", &e),
 			)
 			.await;
 
-			return Err("2");
+			return Err(SyncError::Decode(e));
 		}
 	};
 
 	let hula_project: HulaProjectStructureResponse = jiison2;
 
 	let _ = write_hula_log(
-		conn,
+		pool,
 		Some(&hula_project.id.to_string()),
 		header.id,
 		&request_url,
@@ -729,12 +1972,442 @@ pub async fn insert_hula_project_odoo(
 	Ok(hula_project)
 }
 
+/// Reads a project structure without modifying it, for its match count.
+pub async fn get_hula_project_odoo<T: DeserializeOwned>(
+	pool: &Pool,
+	config: &HulaConfig,
+	project_id: String,
+	project: &OdooProjectHeader,
+) -> Result<T, SyncError> {
+	let request_url = format!("{}/api/projectstructures/{}", config.hula_url, &project_id);
+
+	let client = reqwest::Client::new();
+
+	let response = hulautils::send(
+		config,
+		client
+			.get(&request_url)
+			.header("Cookie", format!("auth={}", config.cookie)),
+	)
+	.await;
+
+	let response = match response {
+		Ok(file) => file,
+		Err(e) => {
+			let _ = write_hula_log(
+				pool,
+				Some(&project_id),
+				project.id,
+				&request_url,
+				"GET",
+				"",
+				0,
+				&format!("{}", &e),
+			)
+			.await;
+
+			return Err(SyncError::Http(e));
+		}
+	};
+
+	let code = response.status();
+	let status: i32 = code.as_u16().into();
+
+	if !code.is_success() {
+		let text = response.text().await.unwrap_or_default();
+		let _ = write_hula_log(
+			pool,
+			Some(&project_id),
+			project.id,
+			&request_url,
+			"GET",
+			"",
+			status,
+			&text,
+		)
+		.await;
+
+		return Err(SyncError::Status(code));
+	}
+
+	match response.json::<T>().await {
+		Ok(hula_project) => Ok(hula_project),
+		Err(e) => {
+			let _ = write_hula_log(
+				pool,
+				Some(&project_id),
+				project.id,
+				&request_url,
+				"GET",
+				"",
+				status,
+				&format!("{}", &e),
+			)
+			.await;
+
+			Err(SyncError::Decode(e))
+		}
+	}
+}
+
+/// Updates the Hula project of a mapped lead. Fields are merged according
+/// to the ownership policy and conflicts no owner settles are parked in
+/// `sync_conflicts`, leaving the project alone until they are resolved.
+/// With reverse sync on, Hula's values are returned as a `LeadUpdate` for
+/// Odoo and the mapping is only marked synced once that is written.
+async fn run_update(
+	config: &HulaConfig,
+	pool: &Pool,
+	reverse: &ReverseConfig,
+	known: &[SyncConflict],
+	change: &Mapped<'_, OdooProjectHeader, OdooProject>,
+	crm: HulaProjectStructureData,
+	hashes: &HashMap<i32, String>,
+) -> JobResult {
+	let deal = change.record;
+
+	let current = get_hula_project_odoo::<HulaProjectStructure>(
+		pool,
+		config,
+		change.project.id.clone(),
+		deal,
+	)
+	.await;
+	let current = match current {
+		Ok(item) => item,
+		Err(e) => return Err(JobError::hula("get_hula_project_odoo", e)),
+	};
+
+	let mut resolved = HashMap::new();
+	let mut resolved_ids = vec![];
+
+	for conflict in known {
+		let owner = match &conflict.status[..] {
+			sync_conflict::KEEP_CRM => Owner::Crm,
+			sync_conflict::KEEP_HULA => Owner::Hula,
+			_ => continue,
+		};
+
+		if let Some(field) = Field::parse(&conflict.field) {
+			resolved.insert(field, owner);
+			resolved_ids.push(conflict.id);
+		}
+	}
+
+	let base: Option<HulaProjectStructureData> = change
+		.mapping
+		.snapshot
+		.as_deref()
+		.and_then(|x| serde_json::from_str(x).ok());
+
+	let merge = reverse_sync::merge(
+		reverse,
+		base.as_ref(),
+		Side {
+			data: &crm,
+			changed: change.mapping.content_hash.as_ref() != hashes.get(&deal.id),
+			updated_at: deal.write_date,
+		},
+		Side {
+			data: &current.data,
+			changed: hula_changed(change.mapping, change.project),
+			updated_at: current
+				.updated_at
+				.as_deref()
+				.and_then(hulautils::parse_timestamp),
+		},
+		&resolved,
+	);
+
+	for conflict in &merge.conflicts {
+		match conflict.winner {
+			Some(winner) => warn!(
+				"Conflict on lead {}: {} was changed in Odoo and Hula, keeping the {} value.",
+				deal.id,
+				conflict.field.name(),
+				match winner {
+					Owner::Hula => "Hula",
+					_ => "Odoo",
+				}
+			),
+			None => warn!(
+				"Conflict on lead {}: {} was changed in Odoo and Hula, parking it for resolution.",
+				deal.id,
+				conflict.field.name()
+			),
+		}
+	}
+
+	if merge.is_parked() {
+		let now = chrono::Utc::now().naive_utc();
+		let parked = merge
+			.conflicts
+			.iter()
+			.filter(|x| x.winner.is_none())
+			.map(|x| SyncConflict {
+				id: uuid::Uuid::new_v4(),
+				source: "odoo".to_string(),
+				mapping_id: change.mapping.id,
+				crm_id: deal.id.to_string(),
+				hula_id: change.hula_id,
+				field: x.field.name().to_string(),
+				crm_value: x.field.value(&crm).to_string(),
+				hula_value: x.field.value(&current.data).to_string(),
+				status: sync_conflict::OPEN.to_string(),
+				updated_by: "hulasync".to_string(),
+				updated_at: now,
+			})
+			.collect();
+
+		if let Err(e) = conflicts::park(pool, parked).await {
+			return Err(format!("Parking conflicts failed: {}", e).into());
+		}
+
+		return Ok((
+			ProjectMatch {
+				id: change.mapping.odoo_id,
+				matches: current.matches,
+				link: hulautils::project_link(config, &change.hula_id),
+				summary: None,
+				mapping_id: change.mapping.id,
+				hula_id: change.hula_id,
+				summary_hash: None,
+			},
+			None,
+		));
+	}
+
+	let mut matches = current.matches;
+
+	if !merge.to_hula.is_empty() {
+		let updated =
+			update_hula_project_odoo(pool, config, change.project.id.clone(), deal, &merge.data)
+				.await;
+		matches = match updated {
+			Ok(item) => item.matches,
+			Err(e) => return Err(JobError::hula("update_hula_project_odoo", e)),
+		};
+	}
+
+	let lead = if merge.to_crm.is_empty() || !reverse.enabled {
+		let stored = update_odoo_log_hash(
+			pool,
+			change.mapping.id,
+			hashes.get(&deal.id),
+			snapshot(&merge.data),
+		)
+		.await;
+		if let Err(e) = stored {
+			error!("update_odoo_log_hash failed: {}", e);
+		}
+		if let Err(e) = conflicts::mark_applied(pool, resolved_ids).await {
+			error!("Marking conflicts applied failed: {}", e);
+		}
+		None
+	} else {
+		Some(LeadUpdate {
+			id: deal.id,
+			values: reverse_sync::lead_values(&merge.data, &merge.to_crm),
+			mapping_id: change.mapping.id,
+			content_hash: content_hash(&merge.data),
+			snapshot: snapshot(&merge.data),
+			resolved: resolved_ids,
+		})
+	};
+
+	Ok((
+		ProjectMatch {
+			id: change.mapping.odoo_id,
+			matches,
+			link: hulautils::project_link(config, &change.hula_id),
+			summary: None,
+			mapping_id: change.mapping.id,
+			hula_id: change.hula_id,
+			summary_hash: None,
+		},
+		lead,
+	))
+}
+
+/// What `odoo_put_leads.py` could not write.
+#[derive(Deserialize)]
+struct LeadWrites {
+	failed: Vec<LeadWriteError>,
+}
+
+#[derive(Deserialize)]
+struct LeadWriteError {
+	id: i32,
+	error: String,
+	/// Odoo refused the values rather than not being reached.
+	#[serde(default)]
+	permanent: bool,
+}
+
+/// Writes Hula-side edits to the Odoo leads, then marks the mappings of the
+/// leads written as synced with the structure the leads now have. Returns
+/// the leads that could not be written; their hash is not stored, so they
+/// are sent again.
+async fn put_odoo_leads(pool: &Pool, leads: &[LeadUpdate]) -> Vec<Failure> {
+	let all_failed = |error: String| -> Vec<Failure> {
+		leads
+			.iter()
+			.map(|x| Failure {
+				id: x.id,
+				payload: x.snapshot.clone(),
+				error: error.clone(),
+				permanent: false,
+			})
+			.collect()
+	};
+
+	let config = match get_config() {
+		Ok(c) => c,
+		Err(e) => return all_failed(e.to_string()),
+	};
+
+	let payload = serde_json::to_string(&serde_json::json!({
+		"max_years_field": config.max_years_field,
+		"leads": leads,
+	}));
+
+	let payload = match payload {
+		Ok(v) => v,
+		Err(e) => return all_failed(format!("Serde failed: {}", e)),
+	};
+
+	let script_path = "src/modules/odoo/python/odoo_put_leads.py";
+	let output = match run_odoo_script(script_path.to_string(), pool, &payload).await {
+		Ok(output) => output,
+		Err(e) => return all_failed(format!("Writing lead failed: {}", e)),
+	};
+
+	let writes: LeadWrites = match serde_json::from_value(output) {
+		Ok(v) => v,
+		Err(e) => return all_failed(format!("{} output was not valid: {}", script_path, e)),
+	};
+
+	let mut errors: HashMap<i32, LeadWriteError> =
+		writes.failed.into_iter().map(|x| (x.id, x)).collect();
+	let mut failures = vec![];
+
+	for lead in leads {
+		if let Some(error) = errors.remove(&lead.id) {
+			failures.push(Failure {
+				id: lead.id,
+				payload: lead.snapshot.clone(),
+				error: format!("Writing lead failed: {}", error.error),
+				permanent: error.permanent,
+			});
+			continue;
+		}
+
+		let stored = update_odoo_log_hash(
+			pool,
+			lead.mapping_id,
+			Some(&lead.content_hash),
+			lead.snapshot.clone(),
+		)
+		.await;
+		if let Err(e) = stored {
+			error!("update_odoo_log_hash failed: {}", e);
+		}
+		if let Err(e) = conflicts::mark_applied(pool, lead.resolved.clone()).await {
+			error!("Marking conflicts applied failed: {}", e);
+		}
+	}
+
+	failures
+}
+
+/// Candidates Hula matched to the project structure of `item`.
+async fn get_hula_matches_odoo(
+	pool: &Pool,
+	config: &HulaConfig,
+	item: &ProjectMatch,
+) -> Result<Vec<HulaMatch>, SyncError> {
+	let project_id = item.hula_id.to_string();
+	let request_url = format!(
+		"{}/api/projectstructures/{}/matches",
+		config.hula_url, &project_id
+	);
+
+	let client = reqwest::Client::new();
+
+	let response = hulautils::send(
+		config,
+		client
+			.get(&request_url)
+			.header("Cookie", format!("auth={}", config.cookie)),
+	)
+	.await;
+
+	let response = match response {
+		Ok(file) => file,
+		Err(e) => {
+			let _ = write_hula_log(
+				pool,
+				Some(&project_id),
+				item.id,
+				&request_url,
+				"GET",
+				"",
+				0,
+				&format!("{}", &e),
+			)
+			.await;
+
+			return Err(SyncError::Http(e));
+		}
+	};
+
+	let code = response.status();
+	let status: i32 = code.as_u16().into();
+
+	if !code.is_success() {
+		let text = response.text().await.unwrap_or_default();
+		let _ = write_hula_log(
+			pool,
+			Some(&project_id),
+			item.id,
+			&request_url,
+			"GET",
+			"",
+			status,
+			&text,
+		)
+		.await;
+
+		return Err(SyncError::Status(code));
+	}
+
+	match response.json::<Vec<HulaMatch>>().await {
+		Ok(candidates) => Ok(candidates),
+		Err(e) => {
+			let _ = write_hula_log(
+				pool,
+				Some(&project_id),
+				item.id,
+				&request_url,
+				"GET",
+				"",
+				status,
+				&format!("{}", &e),
+			)
+			.await;
+
+			Err(SyncError::Decode(e))
+		}
+	}
+}
+
 pub async fn update_hula_project_odoo(
-	conn: &PgConnection,
+	pool: &Pool,
 	config: &HulaConfig,
 	project_id: String,
 	project: &OdooProjectHeader,
-) -> Result<HulaProjectStructureResponse, &'static str> {
+	data: &HulaProjectStructureData,
+) -> Result<HulaProjectStructureResponse, SyncError> {
 	//let c = get_config();
 
 	let request_url = format!(
@@ -745,37 +2418,39 @@ pub async fn update_hula_project_odoo(
 
 	let client = reqwest::Client::new();
 
-	let data: HulaProjectStructureData = project.into();
-
-	let response = client
-		.put(&request_url)
-		.header("Cookie", format!("auth={}", config.cookie))
-		.json(&data)
-		.send()
-		.await;
+	let response = hulautils::send(
+		config,
+		client
+			.put(&request_url)
+			.header("Cookie", format!("auth={}", config.cookie))
+			.json(&data),
+	)
+	.await;
 
 	let response = match response {
 		Ok(file) => {
-			if file.status().as_u16() > 299 {
+			let status = file.status();
+
+			if !status.is_success() {
 				let _ = write_hula_log(
-					conn,
+					pool,
 					Some(&project_id),
 					project.id,
 					&request_url,
 					"PUT",
 					&format!("{:?}", &data),
-					file.status().as_u16().into(),
-					&format!("{}", &file.text().await.unwrap()),
+					status.as_u16().into(),
+					&file.text().await.unwrap_or_default(),
 				)
 				.await;
 
-				return Err("11");
+				return Err(SyncError::Status(status));
 			}
 			file
 		}
 		Err(e) => {
 			let _ = write_hula_log(
-				conn,
+				pool,
 				Some(&project_id),
 				project.id,
 				&request_url,
@@ -786,7 +2461,7 @@ pub async fn update_hula_project_odoo(
 			)
 			.await;
 
-			return Err("1");
+			return Err(SyncError::Http(e));
 		}
 	};
 
@@ -798,23 +2473,23 @@ pub async fn update_hula_project_odoo(
 		Ok(file) => file,
 		Err(e) => {
 			let _ = write_hula_log(
-				conn,
+				pool,
 				Some(&project_id),
 				project.id,
 				&request_url,
 				"PUT",
 				&format!("{:?}", &data),
-				e.status().unwrap().as_u16().into(),
+				status,
 				&format!("{}", &e),
 			)
 			.await;
 
-			return Err("2");
+			return Err(SyncError::Decode(e));
 		}
 	};
 
 	let _ = write_hula_log(
-		conn,
+		pool,
 		Some(&project_id),
 		project.id,
 		&request_url,
@@ -830,8 +2505,10 @@ pub async fn update_hula_project_odoo(
 	Ok(hula_project)
 }
 
+/// One argument per column of `hula_call_log`.
+#[allow(clippy::too_many_arguments)]
 async fn write_hula_log(
-	conn: &PgConnection,
+	pool: &Pool,
 	hula_id: Option<&str>,
 	odoo_id: i32,
 	url: &str,
@@ -839,44 +2516,57 @@ async fn write_hula_log(
 	payload: &str,
 	status: i32,
 	response: &str,
-) -> Result<(), &'static str> {
+) -> Result<(), SyncError> {
 	use crate::schema::hula_call_log::dsl::hula_call_log;
 
-	let hula_id: Option<uuid::Uuid> = match hula_id {
-		Some(id) => Some(uuid::Uuid::parse_str(id).expect("uuid parsing failed")),
-		None => None,
+	let hula_id: Option<uuid::Uuid> = match hula_id.map(uuid::Uuid::parse_str).transpose() {
+		Ok(id) => id,
+		Err(e) => {
+			error!("Call log of lead {} has an invalid Hula id: {}", odoo_id, e);
+			return Err(e.into());
+		}
 	};
 
 	let new_log = HulaCallLog {
 		id: uuid::Uuid::new_v4(),
-		hula_id: hula_id,
-		odoo_id: odoo_id,
+		hula_id,
+		odoo_id,
 		url: url.to_string(),
 		verb: verb.to_string(),
 		payload: payload.to_string(),
-		status: status,
+		status,
 		response: response.to_string(),
 		updated_by: "hulasync".to_string(),
 		updated_at: chrono::Local::now().naive_local(),
 	};
 
-	let rows_inserted = diesel::insert_into(hula_call_log)
-		.values(&new_log)
-		.get_result::<HulaCallLog>(conn);
+	let rows_inserted = db::run(pool, move |conn| {
+		diesel::insert_into(hula_call_log)
+			.values(&new_log)
+			.get_result::<HulaCallLog>(conn)
+	})
+	.await;
 
-	let _: Option<&HulaCallLog> = match &rows_inserted {
-		Ok(a) => Some(a),
+	let rows_inserted = match rows_inserted {
+		Ok(r) => r,
 		Err(e) => {
-			trace!("ERROR. {:?}", e);
-			return Err("failed.");
+			error!("ERROR. {}", e);
+			return Err(e.into());
 		}
 	};
 
-	return Ok(());
+	if let Err(e) = rows_inserted {
+		error!("ERROR. {:?}", e);
+		return Err(e.into());
+	}
+
+	Ok(())
 }
 
+/// One argument per column of `odoo_call_log`.
+#[allow(clippy::too_many_arguments)]
 async fn write_odoo_call_log(
-	conn: &PgConnection,
+	pool: &Pool,
 	script: &str,
 	param1: Option<&str>,
 	param2: Option<&str>,
@@ -898,28 +2588,36 @@ async fn write_odoo_call_log(
 		param4: Some(param4.unwrap_or_default().to_string()),
 		param5: Some(param5.unwrap_or_default().to_string()),
 		param6: Some(param6.unwrap_or_default().to_string()),
-		ok: ok,
+		ok,
 		response: Some(response.unwrap_or_default().to_string()),
 		updated_by: "hulasync".to_string(),
 		updated_at: chrono::Local::now().naive_local(),
 	};
 
-	let rows_inserted = diesel::insert_into(odoo_call_log)
-		.values(&new_log)
-		.get_result::<OdooCallLog>(conn);
+	let rows_inserted = db::run(pool, move |conn| {
+		diesel::insert_into(odoo_call_log)
+			.values(&new_log)
+			.get_result::<OdooCallLog>(conn)
+	})
+	.await;
 
-	let _: Option<&OdooCallLog> = match &rows_inserted {
-		Ok(a) => Some(a),
+	let rows_inserted = match rows_inserted {
+		Ok(r) => r,
 		Err(e) => {
-			error!("ERROR. {:?}", e);
+			error!("ERROR. {}", e);
 			return Err("failed.");
 		}
 	};
 
-	return Ok(());
+	if let Err(e) = rows_inserted {
+		error!("ERROR. {:?}", e);
+		return Err("failed.");
+	}
+
+	Ok(())
 }
 
-async fn startup(conn: &PgConnection) -> Result<Option<i64>, &'static str> {
+async fn startup(pool: &Pool) -> Result<Option<i64>, SyncError> {
 	use crate::schema::hula_call_log::dsl::{hula_call_log, updated_at as hula_updated_at};
 	use crate::schema::odoo_call_log::dsl::{
 		odoo_call_log, ok, param5, updated_at as odoo_updated_at,
@@ -927,23 +2625,40 @@ async fn startup(conn: &PgConnection) -> Result<Option<i64>, &'static str> {
 
 	let discard_limit = chrono::offset::Utc::now().naive_utc() - chrono::Duration::days(7);
 
-	let _ = diesel::delete(odoo_call_log.filter(odoo_updated_at.lt(discard_limit))).execute(conn);
-	let _ = diesel::delete(hula_call_log.filter(hula_updated_at.lt(discard_limit))).execute(conn);
-
-	let log = odoo_call_log
-		.filter(ok.eq(true))
-		.order(odoo_updated_at.desc())
-		.first::<OdooCallLog>(conn)
-		.optional()
-		.unwrap();
-
-	let log_full = odoo_call_log
-		.filter(ok.eq(true))
-		.filter(param5.eq(""))
-		.order(odoo_updated_at.desc())
-		.first::<OdooCallLog>(conn)
-		.optional()
-		.unwrap();
+	let logs = db::run(pool, move |conn| {
+		let _ =
+			diesel::delete(odoo_call_log.filter(odoo_updated_at.lt(discard_limit))).execute(conn);
+		let _ =
+			diesel::delete(hula_call_log.filter(hula_updated_at.lt(discard_limit))).execute(conn);
+
+		let log = odoo_call_log
+			.filter(ok.eq(true))
+			.order(odoo_updated_at.desc())
+			.first::<OdooCallLog>(conn)
+			.optional()?;
+
+		let log_full = odoo_call_log
+			.filter(ok.eq(true))
+			.filter(param5.eq(""))
+			.order(odoo_updated_at.desc())
+			.first::<OdooCallLog>(conn)
+			.optional()?;
+
+		Ok::<_, diesel::result::Error>((log, log_full))
+	})
+	.await;
+
+	let (log, log_full) = match logs {
+		Ok(Ok(v)) => v,
+		Ok(Err(e)) => {
+			error!("Reading the Odoo call log failed: {}", e);
+			return Err(e.into());
+		}
+		Err(e) => {
+			error!("ERROR. {}", e);
+			return Err(e.into());
+		}
+	};
 
 	if let Some(log) = log {
 		let mut x = chrono::Datelike::num_days_from_ce(&log.updated_at);
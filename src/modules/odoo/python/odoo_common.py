@@ -0,0 +1,121 @@
+import json
+import os
+import sys
+import traceback
+import xmlrpc.client
+
+# Connection details come from the environment and the payload from stdin,
+# so that neither is visible in the process list.
+url = os.environ['ODOO_URL']
+db = os.environ['ODOO_DB']
+username = os.environ['ODOO_USERNAME']
+password = os.environ['ODOO_PASSWORD']
+
+
+def read_payload():
+    text = sys.stdin.read()
+    return json.loads(text) if text.strip() else None
+
+
+def connect():
+    common = xmlrpc.client.ServerProxy('{}/xmlrpc/2/common'.format(url))
+    uid = common.authenticate(db, username, password, {})
+    models = xmlrpc.client.ServerProxy('{}/xmlrpc/2/object'.format(url))
+    return uid, models
+
+
+def respond(main):
+    """Runs main(payload) and prints the result as {"ok", "data", "error"}."""
+    try:
+        data = main(read_payload())
+    except Exception as e:
+        traceback.print_exc(file=sys.stderr)
+        print(json.dumps({'ok': False, 'data': None, 'error': '{}: {}'.format(type(e).__name__, e)}))
+        sys.exit(1)
+
+    print(json.dumps({'ok': True, 'data': data, 'error': None}))
+
+
+def ensure_sync_fields(models, uid, model, extra_fields=()):
+    """Adds x_hula_id, the given (name, type, label) fields and, if the model
+    has no archive field, x_active to model. Returns the archive field."""
+    fields = models.execute_kw(db, uid, password, model, 'fields_get', [], {'attributes': ['type']})
+
+    wanted = [('x_hula_id', 'char', 'Hula ID'), *extra_fields]
+    missing = [f for f in wanted if f[0] not in fields]
+    active_field = 'active' if 'active' in fields else 'x_active'
+    if active_field not in fields:
+        missing.append((active_field, 'boolean', 'Active'))
+
+    if not missing:
+        return active_field
+
+    # Taken before x_active exists, while every record is still visible.
+    existing_ids = models.execute_kw(db, uid, password, model, 'search', [[]])
+
+    model_id = models.execute_kw(db, uid, password, 'ir.model', 'search', [[['model', '=', model]]])[0]
+    for name, ttype, description in missing:
+        models.execute_kw(db, uid, password, 'ir.model.fields', 'create', [{
+            'model_id': model_id,
+            'name': name,
+            'ttype': ttype,
+            'field_description': description,
+        }])
+
+    if active_field == 'x_active' and existing_ids:
+        models.execute_kw(db, uid, password, model, 'write', [existing_ids, {'x_active': True}])
+
+    return active_field
+
+
+def reconcile_records(models, uid, model, name_field, active_field, items):
+    """Makes the records of model match items, dicts with x_hula_id, the
+    name under name_field and any other field values. Records are keyed on
+    x_hula_id; records without one are adopted by name. Records whose
+    x_hula_id is not in items are archived. Returns what was changed."""
+    other_fields = sorted({k for item in items for k in item} - {'x_hula_id', name_field})
+
+    records = models.execute_kw(db, uid, password,
+        model, 'search_read', [[]], {
+            'fields': ['id', name_field, 'x_hula_id', active_field, *other_fields],
+            'context': {'active_test': False},
+        })
+
+    by_hula_id = {r['x_hula_id']: r for r in records if r['x_hula_id']}
+    unkeyed_by_name = {r[name_field].lower(): r for r in records if not r['x_hula_id'] and r[name_field]}
+
+    summary = {'created': [], 'renamed': [], 'archived': [], 'restored': [], 'changed': []}
+
+    for item in items:
+        name = item[name_field]
+        record = by_hula_id.get(item['x_hula_id']) or unkeyed_by_name.pop(name.lower(), None)
+
+        if not record:
+            models.execute_kw(db, uid, password, model, 'create', [item])
+            summary['created'].append(name)
+            continue
+
+        values = {}
+        if record['x_hula_id'] != item['x_hula_id']:
+            values['x_hula_id'] = item['x_hula_id']
+        if record[name_field] != name:
+            values[name_field] = name
+            summary['renamed'].append('{} -> {}'.format(record[name_field], name))
+        changed = {k: item[k] for k in other_fields if k in item and (record[k] or '') != item[k]}
+        if changed:
+            values.update(changed)
+            summary['changed'].append(name)
+        if not record[active_field]:
+            values[active_field] = True
+            summary['restored'].append(name)
+
+        if values:
+            models.execute_kw(db, uid, password, model, 'write', [[record['id']], values])
+
+    hula_ids = {item['x_hula_id'] for item in items}
+    removed = [r for r in by_hula_id.values() if r['x_hula_id'] not in hula_ids and r[active_field]]
+    if removed:
+        models.execute_kw(db, uid, password, model, 'write', [[r['id'] for r in removed], {active_field: False}])
+        summary['archived'] = [r[name_field] for r in removed]
+
+    return summary
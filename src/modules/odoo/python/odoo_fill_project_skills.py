@@ -1,21 +1,10 @@
-import sys 
-import json
 from datetime import datetime, date
 from typing import Optional, List
-import jsonpickle
 import ahocorasick
 
-url = str(sys.argv[1])
-db = str(sys.argv[2])
-username = str(sys.argv[3])
-password = str(sys.argv[4])
-hula_skills = json.loads(str(sys.argv[5]))
+from odoo_common import db, password, connect, respond
 
-import xmlrpc.client
-
-common = xmlrpc.client.ServerProxy('{}/xmlrpc/2/common'.format(url))
-uid = common.authenticate(db, username, password, {})
-models = xmlrpc.client.ServerProxy('{}/xmlrpc/2/object'.format(url))
+uid, models = connect()
 
 class MergedSkill(object):
     def __init__(self, hula_id: str, odoo_id: str, label: str, aliases: Optional[List[str]]) -> None:
@@ -109,13 +98,14 @@ def generate_project_name(lead):
 
 
 def merge_skills(hula_skills, odoo_skills):
+    hula_by_id = {s['id']: s for s in hula_skills}
     hula_dict = {s['label'].lower(): s for s in hula_skills}
     odoo_dict = {s['x_name'].lower(): s for s in odoo_skills}
 
     merged_skills = []
     for label in odoo_dict.keys():
         odoo_skill = odoo_dict[label]
-        hula_skill = hula_dict.get(label, None)
+        hula_skill = hula_by_id.get(odoo_skill.get('x_hula_id')) or hula_dict.get(label, None)
         if not hula_skill:
             continue
 
@@ -129,7 +119,7 @@ def merge_skills(hula_skills, odoo_skills):
     return merged_skills
 
 
-def main():
+def main(hula_skills):
     leads = models.execute_kw(db, uid, password,
         'crm.lead', 'search_read',
         [[['x_studio_disable_skill_generation', '!=', True]]],
@@ -145,7 +135,7 @@ def main():
 
 
     odoo_skills = models.execute_kw(db, uid, password,
-        'x_hula_skill', 'search_read', [], {'fields': ['id', 'display_name', 'x_name']})
+        'x_hula_skill', 'search_read', [], {'fields': ['id', 'display_name', 'x_name', 'x_hula_id']})
 
     merged_skills = merge_skills(hula_skills, odoo_skills)
 
@@ -167,8 +157,7 @@ def main():
         begin_date = generate_begin_date(lead)
         update_lead(lead['id'], found_skills, project_name=project_name, begin_date=begin_date)
 
-    lead_names = [x['name'] for x in leads]
-    print(jsonpickle.encode(lead_names, unpicklable=False))
+    return [x['name'] for x in leads]
 
 
-main()
\ No newline at end of file
+respond(main)
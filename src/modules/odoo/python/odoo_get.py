@@ -1,16 +1,9 @@
 import json
-import sys
 import jsonpickle
 from datetime import timedelta
 from datetime import datetime
 
-url = str(sys.argv[1])
-db = str(sys.argv[2])
-username = str(sys.argv[3])
-password = str(sys.argv[4])
-lastrun = str(sys.argv[5])
-
-import xmlrpc.client
+from odoo_common import db, password, connect, respond
 
 class Project(object):
         id :str = ""
@@ -18,156 +11,200 @@ class Project(object):
         description :str = ""
         visible :bool = False
         needs = []
+        write_date :str = None
 
 class ProjectNeed(object):
         label :str = ""
         nbr :int = ""
         begin :datetime
         end :datetime
+        percentage :int = None
         skills = []
 
 class ProjectNeedSkill(object):
         skill :str = ""
         level :str = ""
         min_years :int
+        max_years :int = None
         mandatory :bool
 
-if str(lastrun):
-        t = datetime.utcnow() - timedelta(minutes=int(lastrun))
-        limit = t.strftime('%Y-%m-%d %H:%M:%S')
-else:
-        t = datetime.min
-        limit = t.strftime('%Y-%m-%d %H:%M:%S')
-
-common = xmlrpc.client.ServerProxy('{}/xmlrpc/2/common'.format(url))
-
-uid = common.authenticate(db, username, password, {})
-
-models = xmlrpc.client.ServerProxy('{}/xmlrpc/2/object'.format(url))
-c = models.execute_kw(db, uid, password,
-    'crm.lead', 'search_read',
-    [[['write_date', '>', limit]]],
-    {'fields': ['id', 
-        'name',
-        'x_studio_project_name', 
-        'x_studio_description', 
-        'x_studio_begin', 
-        'x_studio_end', 
-        'x_studio_nbr_of_positions', 
-        'x_studio_skill_1', 
-        'x_studio_level_1', 
-        'x_studio_minimum_years_1', 
-        'x_studio_mandatory_1', 
-        'x_studio_skill_2', 
-        'x_studio_level_2', 
-        'x_studio_minimum_years_2', 
-        'x_studio_mandatory_2',
-        'x_studio_skill_3', 
-        'x_studio_level_3', 
-        'x_studio_minimum_years_3', 
-        'x_studio_mandatory_3', 
-        'x_studio_skill_4', 
-        'x_studio_level_4', 
-        'x_studio_minimum_years_4', 
-        'x_studio_mandatory_4',
-        'x_studio_role',
-        'x_studio_visible'],
-        'limit': 5000})
-
-res = []
-
-for cc in c:
-    if not cc['x_studio_skill_1']:
-            continue
-
-    if isinstance(cc['x_studio_begin'], bool):
-            continue
-
-    if not cc['x_studio_project_name']:
-            cc['x_studio_project_name'] = 'Odoo project: ' + str(cc['id'])
-
-    if not cc['x_studio_description']:
-            cc['x_studio_description'] = cc['x_studio_project_name']
-
-    if not cc['x_studio_role']:
-            cc['x_studio_role'] = cc['x_studio_project_name']
-
-    if isinstance(cc['x_studio_level_1'], bool):
-            cc['x_studio_level_1'] = None
-    else:
-            cc['x_studio_level_1'] = cc['x_studio_level_1'][1]                        
+def max_years(lead, field):
+    if not field:
+        return None
+    # An empty numeric field reads as 0, which means no cap.
+    return lead[field] or None
 
-    if isinstance(cc['x_studio_level_2'], bool):
-            cc['x_studio_level_2'] = None
-    else:
-            cc['x_studio_level_2'] = cc['x_studio_level_2'][1]                        
 
-    if isinstance(cc['x_studio_level_3'], bool):
-            cc['x_studio_level_3'] = None
-    else:
-            cc['x_studio_level_3'] = cc['x_studio_level_3'][1]                        
+def optional_fields(models, uid, payload):
+    """The configured percentage field and the maximum years field of each
+    skill slot, None where crm.lead has no such field."""
+    percentage_field = payload.get('percentage_field')
+    max_years_field = payload.get('max_years_field')
+
+    slots = [max_years_field.replace('{n}', str(n)) if max_years_field else None for n in range(1, 5)]
+    wanted = [percentage_field or None, *slots]
+
+    names = [f for f in wanted if f]
+    existing = models.execute_kw(db, uid, password, 'crm.lead', 'fields_get',
+        [names], {'attributes': ['type']}) if names else {}
+
+    found = [f if f in existing else None for f in wanted]
+    return found[0], found[1:]
+
+
+def main(payload):
+    payload = payload or {}
+    lastrun = payload.get('last_run')
+    ids = payload.get('ids') or []
 
-    if isinstance(cc['x_studio_level_4'], bool):
-            cc['x_studio_level_4'] = None
+    if lastrun is not None:
+            t = datetime.utcnow() - timedelta(minutes=int(lastrun))
+            limit = t.strftime('%Y-%m-%d %H:%M:%S')
     else:
-            cc['x_studio_level_4'] = cc['x_studio_level_4'][1]                        
-
-    if isinstance(cc['x_studio_end'], bool):
-            cc['x_studio_end'] = None
-
-
-    skills = []
-    if cc['x_studio_skill_1']:
-        skill = ProjectNeedSkill()
-        skill.skill = cc['x_studio_skill_1'][1]
-        skill.level = cc['x_studio_level_1']
-        skill.min_years = cc['x_studio_minimum_years_1'] 
-        skill.mandatory = cc['x_studio_mandatory_1']
-        skills.append(skill)
-
-    if cc['x_studio_skill_2']:
-        skill2 = ProjectNeedSkill()
-        skill2.skill = cc['x_studio_skill_2'][1]
-        skill2.level = cc['x_studio_level_2']
-        skill2.min_years = cc['x_studio_minimum_years_2'] 
-        skill2.mandatory = cc['x_studio_mandatory_2']
-        skills.append(skill2)
-
-    if cc['x_studio_skill_3']:
-        skill3 = ProjectNeedSkill()
-        skill3.skill = cc['x_studio_skill_3'][1]
-        skill3.level = cc['x_studio_level_3']
-        skill3.min_years = cc['x_studio_minimum_years_3'] 
-        skill3.mandatory = cc['x_studio_mandatory_3']
-        skills.append(skill3)
-
-    if cc['x_studio_skill_4']:
-        skill4 = ProjectNeedSkill()
-        skill4.skill = cc['x_studio_skill_4'][1]
-        skill4.level = cc['x_studio_level_4']
-        skill4.min_years = cc['x_studio_minimum_years_4'] 
-        skill4.mandatory = cc['x_studio_mandatory_4']
-        skills.append(skill4)
-
-    needs = []
-    need = ProjectNeed()
-    need.label = cc['x_studio_role']
-    need.nbr = cc['x_studio_nbr_of_positions']
-    need.begin = cc['x_studio_begin']
-    need.end = cc['x_studio_end']
-    need.skills = skills
-
-    proj = Project()
-    proj.id = cc['id']
-    proj.name = cc['x_studio_project_name']
-    proj.description = cc['x_studio_description']
-    proj.visible = cc['x_studio_visible']
-    proj.needs = [need]
-
-    res.append(proj)
-
-print(jsonpickle.encode(res, unpicklable=False))
-
-#for r in res:
-#    print(jsonpickle.encode(r, unpicklable=False))
+            t = datetime.min
+            limit = t.strftime('%Y-%m-%d %H:%M:%S')
+
+    domain = [['write_date', '>', limit]]
+
+    # Leads the sync has to see again even though they did not change.
+    if lastrun is not None and ids:
+            domain = ['|', *domain, ['id', 'in', ids]]
+
+    uid, models = connect()
+    percentage_field, max_years_fields = optional_fields(models, uid, payload)
+    extra_fields = [f for f in [percentage_field, *max_years_fields] if f]
+
+    c = models.execute_kw(db, uid, password,
+        'crm.lead', 'search_read',
+        [domain],
+        {'fields': ['id', 
+            'name',
+            'x_studio_project_name', 
+            'x_studio_description', 
+            'x_studio_begin', 
+            'x_studio_end', 
+            'x_studio_nbr_of_positions', 
+            'x_studio_skill_1', 
+            'x_studio_level_1', 
+            'x_studio_minimum_years_1', 
+            'x_studio_mandatory_1', 
+            'x_studio_skill_2', 
+            'x_studio_level_2', 
+            'x_studio_minimum_years_2', 
+            'x_studio_mandatory_2',
+            'x_studio_skill_3', 
+            'x_studio_level_3', 
+            'x_studio_minimum_years_3', 
+            'x_studio_mandatory_3', 
+            'x_studio_skill_4', 
+            'x_studio_level_4', 
+            'x_studio_minimum_years_4', 
+            'x_studio_mandatory_4',
+            'x_studio_role',
+            'x_studio_visible',
+            'write_date',
+            *extra_fields],
+            'limit': 5000})
+
+    res = []
+
+    for cc in c:
+        if not cc['x_studio_skill_1']:
+                continue
+
+        if isinstance(cc['x_studio_begin'], bool):
+                continue
+
+        if not cc['x_studio_project_name']:
+                cc['x_studio_project_name'] = 'Odoo project: ' + str(cc['id'])
+
+        if not cc['x_studio_description']:
+                cc['x_studio_description'] = cc['x_studio_project_name']
+
+        if not cc['x_studio_role']:
+                cc['x_studio_role'] = cc['x_studio_project_name']
+
+        if isinstance(cc['x_studio_level_1'], bool):
+                cc['x_studio_level_1'] = None
+        else:
+                cc['x_studio_level_1'] = cc['x_studio_level_1'][1]                        
+
+        if isinstance(cc['x_studio_level_2'], bool):
+                cc['x_studio_level_2'] = None
+        else:
+                cc['x_studio_level_2'] = cc['x_studio_level_2'][1]                        
+
+        if isinstance(cc['x_studio_level_3'], bool):
+                cc['x_studio_level_3'] = None
+        else:
+                cc['x_studio_level_3'] = cc['x_studio_level_3'][1]                        
+
+        if isinstance(cc['x_studio_level_4'], bool):
+                cc['x_studio_level_4'] = None
+        else:
+                cc['x_studio_level_4'] = cc['x_studio_level_4'][1]                        
+
+        if isinstance(cc['x_studio_end'], bool):
+                cc['x_studio_end'] = None
+
+
+        skills = []
+        if cc['x_studio_skill_1']:
+            skill = ProjectNeedSkill()
+            skill.skill = cc['x_studio_skill_1'][1]
+            skill.level = cc['x_studio_level_1']
+            skill.min_years = cc['x_studio_minimum_years_1'] 
+            skill.max_years = max_years(cc, max_years_fields[0])
+            skill.mandatory = cc['x_studio_mandatory_1']
+            skills.append(skill)
+
+        if cc['x_studio_skill_2']:
+            skill2 = ProjectNeedSkill()
+            skill2.skill = cc['x_studio_skill_2'][1]
+            skill2.level = cc['x_studio_level_2']
+            skill2.min_years = cc['x_studio_minimum_years_2'] 
+            skill2.max_years = max_years(cc, max_years_fields[1])
+            skill2.mandatory = cc['x_studio_mandatory_2']
+            skills.append(skill2)
+
+        if cc['x_studio_skill_3']:
+            skill3 = ProjectNeedSkill()
+            skill3.skill = cc['x_studio_skill_3'][1]
+            skill3.level = cc['x_studio_level_3']
+            skill3.min_years = cc['x_studio_minimum_years_3'] 
+            skill3.max_years = max_years(cc, max_years_fields[2])
+            skill3.mandatory = cc['x_studio_mandatory_3']
+            skills.append(skill3)
+
+        if cc['x_studio_skill_4']:
+            skill4 = ProjectNeedSkill()
+            skill4.skill = cc['x_studio_skill_4'][1]
+            skill4.level = cc['x_studio_level_4']
+            skill4.min_years = cc['x_studio_minimum_years_4'] 
+            skill4.max_years = max_years(cc, max_years_fields[3])
+            skill4.mandatory = cc['x_studio_mandatory_4']
+            skills.append(skill4)
+
+        needs = []
+        need = ProjectNeed()
+        need.label = cc['x_studio_role']
+        need.nbr = cc['x_studio_nbr_of_positions']
+        need.begin = cc['x_studio_begin']
+        need.end = cc['x_studio_end']
+        need.percentage = int(cc[percentage_field]) if percentage_field and cc[percentage_field] else None
+        need.skills = skills
+
+        proj = Project()
+        proj.id = cc['id']
+        proj.name = cc['x_studio_project_name']
+        proj.description = cc['x_studio_description']
+        proj.visible = cc['x_studio_visible']
+        proj.needs = [need]
+        proj.write_date = cc['write_date'].replace(' ', 'T')
+
+        res.append(proj)
+
+    return json.loads(jsonpickle.encode(res, unpicklable=False))
+
 
+respond(main)
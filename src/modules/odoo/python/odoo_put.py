@@ -1,35 +1,97 @@
-import json
-import sys
+import html
 
-url = str(sys.argv[1])
-db = str(sys.argv[2])
-username = str(sys.argv[3])
-password = str(sys.argv[4])
-matches = json.loads(str(sys.argv[5]))
+from odoo_common import db, password, connect, respond
 
-import xmlrpc.client
 
-common = xmlrpc.client.ServerProxy('{}/xmlrpc/2/common'.format(url))
+def language_codes(models, uid, leads):
+        """Maps res.lang ids used by the leads to language codes like fi_FI."""
+        ids = list({lead['lang_id'][0] for lead in leads if lead.get('lang_id')})
+        if not ids:
+                return {}
 
-uid = common.authenticate(db, username, password, {})
+        langs = models.execute_kw(db, uid, password, 'res.lang', 'read', [ids], {'fields': ['code']})
+        return {lang['id']: lang['code'] for lang in langs}
 
-models = xmlrpc.client.ServerProxy('{}/xmlrpc/2/object'.format(url))
 
-for match in matches:
-        nbr_of_matches = match["matches"]
-        link = '<a href="' + match["link"] + '" target="_blank">Link to project comes here</a>'
+def main(payload):
+        uid, models = connect()
 
+        matches = payload['matches']
+        link_text = payload['link_text']
+        link_texts = payload.get('link_texts') or {}
+        summary_field = payload.get('summary_field')
+        summary_note = payload.get('summary_note')
+        error_field = payload.get('error_field')
+        errors = payload.get('errors') or []
+
+        fields = ['x_studio_nbr_of_matches', 'x_studio_link']
+        if link_texts:
+                fields.append('lang_id')
+        if error_field:
+                fields.append(error_field)
+
+        ids = [match["id"] for match in matches] + [error["id"] for error in errors]
         existing = models.execute_kw(db, uid, password,
                 'crm.lead', 'search_read',
-                [[['id', '=', match["id"]]]],
-                {'fields': ['x_studio_nbr_of_matches', 
-                        'x_studio_link']})
+                [[['id', 'in', ids]]],
+                {'fields': fields})
+        existing = {lead['id']: lead for lead in existing}
+
+        codes = language_codes(models, uid, existing.values()) if link_texts else {}
+
+        updated = []
+
+        for match in matches:
+                nbr_of_matches = match["matches"]
+                lead = existing.get(match["id"])
+
+                text = link_text
+                if lead and lead.get('lang_id'):
+                        text = link_texts.get(codes.get(lead['lang_id'][0]), link_text)
+
+                link = '<a href="{}" target="_blank">{}</a>'.format(
+                        html.escape(match["link"], quote=True), html.escape(text))
+
+                # Only present when the summary changed since it was last written.
+                summary = match.get("summary")
+
+                if summary is not None and summary_note:
+                        body = '<p>Hula matches:<br/>{}</p>'.format(
+                                '<br/>'.join(html.escape(line) for line in summary.split('\n')))
+                        models.execute_kw(db, uid, password, 'crm.lead', 'message_post', [[match["id"]]], {
+                                'body': body
+                        })
+
+                values = {}
 
-        if len(existing) > 0:
-                if existing[0]['x_studio_nbr_of_matches'] == nbr_of_matches and existing[0]['x_studio_link']:
+                if not lead or lead['x_studio_nbr_of_matches'] != nbr_of_matches or lead['x_studio_link'] != link:
+                        values['x_studio_nbr_of_matches'] = nbr_of_matches
+                        values['x_studio_link'] = link
+
+                if summary is not None and summary_field:
+                        values[summary_field] = summary
+
+                # The lead synced, so an error from an earlier run is fixed.
+                if error_field and lead and lead.get(error_field):
+                        values[error_field] = False
+
+                if not values:
                         continue
 
-        models.execute_kw(db, uid, password, 'crm.lead', 'write', [[match["id"]], {
-                'x_studio_nbr_of_matches': nbr_of_matches,
-                'x_studio_link': link
-        }])
+                models.execute_kw(db, uid, password, 'crm.lead', 'write', [[match["id"]], values])
+                updated.append(match["id"])
+
+        for error in errors if error_field else []:
+                lead = existing.get(error["id"])
+                if lead and lead.get(error_field) == error["error"]:
+                        continue
+
+                models.execute_kw(db, uid, password, 'crm.lead', 'write', [[error["id"]], {
+                        error_field: error["error"]
+                }])
+                updated.append(error["id"])
+
+        return updated
+
+
+respond(main)
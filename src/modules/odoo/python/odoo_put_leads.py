@@ -0,0 +1,87 @@
+import xmlrpc.client
+
+from odoo_common import db, password, connect, respond
+
+SKILL_SLOTS = 4
+
+
+def relations(models, uid):
+        """Models the skill and level fields of a lead point to."""
+        fields = models.execute_kw(db, uid, password, 'crm.lead', 'fields_get',
+                [['x_studio_skill_1', 'x_studio_level_1']], {'attributes': ['relation']})
+        return fields['x_studio_skill_1']['relation'], fields['x_studio_level_1']['relation']
+
+
+def find_id(models, uid, model, name):
+        if not name:
+                return False
+
+        found = models.execute_kw(db, uid, password, model, 'name_search', [name], {'operator': '=ilike', 'limit': 1})
+        if not found:
+                raise ValueError('{} not found in {}'.format(name, model))
+
+        return found[0][0]
+
+
+def max_years_fields(models, uid, pattern):
+        """The maximum years field of each skill slot, None where crm.lead
+        has no such field."""
+        if not pattern:
+                return [None] * SKILL_SLOTS
+
+        names = [pattern.replace('{n}', str(n)) for n in range(1, SKILL_SLOTS + 1)]
+        existing = models.execute_kw(db, uid, password, 'crm.lead', 'fields_get', [names], {'attributes': ['type']})
+        return [name if name in existing else None for name in names]
+
+
+def skill_values(models, uid, skills, max_years_field):
+        skill_model, level_model = relations(models, uid)
+        max_fields = max_years_fields(models, uid, max_years_field)
+        values = {}
+
+        for idx in range(SKILL_SLOTS):
+                skill = skills[idx] if idx < len(skills) else None
+                n = idx + 1
+
+                # Note: 'None' value is not accepted by Odoo by default, False used instead
+                values['x_studio_skill_{}'.format(n)] = find_id(models, uid, skill_model, skill['skill_label']) if skill else False
+                values['x_studio_level_{}'.format(n)] = find_id(models, uid, level_model, skill['skillscopelevel_label']) if skill else False
+                values['x_studio_minimum_years_{}'.format(n)] = (skill['min_years'] or 0) if skill else 0
+                values['x_studio_mandatory_{}'.format(n)] = skill['mandatory'] if skill else False
+                if max_fields[idx]:
+                        values[max_fields[idx]] = (skill['max_years'] or 0) if skill else 0
+
+        return values
+
+
+def main(payload):
+        uid, models = connect()
+        leads = payload['leads']
+        max_years_field = payload.get('max_years_field')
+
+        updated = []
+        failed = []
+
+        # Each lead is written on its own, so a label that cannot be resolved
+        # or a rejected write only fails that lead.
+        for lead in leads:
+                try:
+                        values = dict(lead['values'])
+
+                        if 'skills' in values:
+                                values.update(skill_values(models, uid, values.pop('skills'), max_years_field))
+
+                        models.execute_kw(db, uid, password, 'crm.lead', 'write', [[lead['id']], values])
+                        updated.append(lead['id'])
+                except Exception as e:
+                        failed.append({
+                                'id': lead['id'],
+                                'error': '{}: {}'.format(type(e).__name__, e),
+                                # Odoo refused the values, as opposed to not being reached.
+                                'permanent': isinstance(e, (ValueError, xmlrpc.client.Fault)),
+                        })
+
+        return {'updated': updated, 'failed': failed}
+
+
+respond(main)
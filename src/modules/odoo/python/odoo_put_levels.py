@@ -0,0 +1,25 @@
+from odoo_common import db, password, connect, respond, ensure_sync_fields, reconcile_records
+
+
+def level_model(models, uid):
+    """The model the level fields of a lead point to."""
+    fields = models.execute_kw(db, uid, password, 'crm.lead', 'fields_get',
+        [['x_studio_level_1']], {'attributes': ['relation']})
+    return fields['x_studio_level_1']['relation']
+
+
+def main(levels):
+    uid, models = connect()
+    model = level_model(models, uid)
+    active_field = ensure_sync_fields(models, uid, model)
+
+    # Studio models name records in x_name, others in name.
+    fields = models.execute_kw(db, uid, password, model, 'fields_get', [], {'attributes': ['type']})
+    name_field = 'x_name' if 'x_name' in fields else 'name'
+
+    items = [{'x_hula_id': level['id'], name_field: level['label']} for level in levels]
+
+    return reconcile_records(models, uid, model, name_field, active_field, items)
+
+
+respond(main)
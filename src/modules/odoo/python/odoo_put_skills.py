@@ -1,34 +1,19 @@
-import json
-import sys
+from odoo_common import connect, respond, ensure_sync_fields, reconcile_records
 
-url = str(sys.argv[1])
-db = str(sys.argv[2])
-username = str(sys.argv[3])
-password = str(sys.argv[4])
-skills = json.loads(str(sys.argv[5]))
+MODEL = 'x_hula_skill'
 
-import xmlrpc.client
 
-common = xmlrpc.client.ServerProxy('{}/xmlrpc/2/common'.format(url))
+def main(skills):
+    uid, models = connect()
+    active_field = ensure_sync_fields(models, uid, MODEL, [('x_aliases', 'char', 'Aliases')])
 
-uid = common.authenticate(db, username, password, {})
+    items = [{
+        'x_hula_id': skill['id'],
+        'x_name': skill['label'],
+        'x_aliases': ', '.join(skill.get('aliases') or []),
+    } for skill in skills]
 
-models = xmlrpc.client.ServerProxy('{}/xmlrpc/2/object'.format(url))
-odoo_skills = models.execute_kw(db, uid, password,
-    'x_hula_skill', 'search_read', [], {'fields': ['id', 'display_name']})
-lowercased_label_to_odoo_skill = {s['display_name'].lower(): s for s in odoo_skills}
+    return reconcile_records(models, uid, MODEL, 'x_name', active_field, items)
 
-created_skills = []
-for skill in skills:
-    label = skill['label']
-    lowercased_label = label.lower()
 
-    found_odoo_skill = lowercased_label_to_odoo_skill.get(lowercased_label, None)
-    if not found_odoo_skill:
-        models.execute_kw(db, uid, password, 'x_hula_skill', 'create', [{
-            'x_name': label,
-            'display_name': label
-        }])
-        created_skills.append(label)
-
-print(json.dumps(created_skills))
+respond(main)
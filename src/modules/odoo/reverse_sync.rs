@@ -0,0 +1,628 @@
+use chrono::NaiveDateTime;
+use log::warn;
+use std::collections::HashMap;
+
+use super::odoo_module::{
+	HulaProjectStructureData, HulaProjectStructureNeedData, HulaProjectStructureNeedSkillData,
+};
+
+/// Which side's value a field takes when CRM and Hula differ.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Owner {
+	/// Odoo is the source of truth, Hula edits are overwritten.
+	Crm,
+	/// Hula is the source of truth, Odoo edits are overwritten.
+	Hula,
+	/// The side that changed since the last sync wins. If both changed the
+	/// conflict is parked in `sync_conflicts`.
+	LastWriter,
+}
+
+impl Owner {
+	fn parse(value: &str) -> Option<Owner> {
+		match value {
+			"crm" => Some(Owner::Crm),
+			"hula" => Some(Owner::Hula),
+			"last" => Some(Owner::LastWriter),
+			_ => None,
+		}
+	}
+}
+
+/// Fields of a lead compared between the sides. Odoo leads carry a single
+/// need, so the need fields refer to the first need of the project. The
+/// percentage and maximum years live in optional Odoo fields and are owned
+/// by Odoo, see `Field::crm_owned`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Field {
+	Name,
+	Visible,
+	Role,
+	Headcount,
+	Begin,
+	End,
+	Skills,
+	Percentage,
+	MaxYears,
+}
+
+impl Field {
+	pub const ALL: [Field; 9] = [
+		Field::Name,
+		Field::Visible,
+		Field::Role,
+		Field::Headcount,
+		Field::Begin,
+		Field::End,
+		Field::Skills,
+		Field::Percentage,
+		Field::MaxYears,
+	];
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Field::Name => "name",
+			Field::Visible => "visible",
+			Field::Role => "role",
+			Field::Headcount => "headcount",
+			Field::Begin => "begin",
+			Field::End => "end",
+			Field::Skills => "skills",
+			Field::Percentage => "percentage",
+			Field::MaxYears => "max_years",
+		}
+	}
+
+	/// Fields only Odoo writes. They are not written back to Odoo, so Hula
+	/// edits are always overwritten.
+	pub fn crm_owned(&self) -> bool {
+		matches!(self, Field::Percentage | Field::MaxYears)
+	}
+
+	pub fn parse(name: &str) -> Option<Field> {
+		Field::ALL.iter().copied().find(|x| x.name() == name)
+	}
+
+	/// The field's value in `data`, as stored with conflicts.
+	pub fn value(&self, data: &HulaProjectStructureData) -> serde_json::Value {
+		use serde_json::json;
+
+		let need = match self {
+			Field::Name => return json!(data.name),
+			Field::Visible => return json!(!data.is_hidden),
+			_ => match data.needs.first() {
+				Some(need) => need,
+				None => return serde_json::Value::Null,
+			},
+		};
+
+		match self {
+			Field::Role => json!(need.label),
+			Field::Headcount => json!(need.count_of_users),
+			Field::Begin => json!(need.begin_time),
+			Field::End => json!(need.end_time),
+			Field::Skills => json!(need.skills),
+			Field::Percentage => json!(need.percentage),
+			Field::MaxYears => json!(max_years(need)),
+			Field::Name | Field::Visible => serde_json::Value::Null,
+		}
+	}
+
+	fn same(&self, a: &HulaProjectStructureData, b: &HulaProjectStructureData) -> bool {
+		match self {
+			Field::Name => a.name == b.name,
+			Field::Visible => a.is_hidden == b.is_hidden,
+			_ => match (a.needs.first(), b.needs.first()) {
+				(Some(x), Some(y)) => self.same_need(x, y),
+				(None, None) => true,
+				_ => false,
+			},
+		}
+	}
+
+	fn same_need(
+		&self,
+		a: &HulaProjectStructureNeedData,
+		b: &HulaProjectStructureNeedData,
+	) -> bool {
+		match self {
+			Field::Role => a.label == b.label,
+			Field::Headcount => a.count_of_users == b.count_of_users,
+			Field::Begin => a.begin_time == b.begin_time,
+			Field::End => a.end_time == b.end_time,
+			Field::Skills => skill_keys(a) == skill_keys(b),
+			Field::Percentage => a.percentage == b.percentage,
+			Field::MaxYears => max_years(a) == max_years(b),
+			Field::Name | Field::Visible => true,
+		}
+	}
+
+	/// Copies this field from `from` into `to`.
+	fn copy(&self, from: &HulaProjectStructureData, to: &mut HulaProjectStructureData) {
+		match self {
+			Field::Name => to.name = from.name.clone(),
+			Field::Visible => to.is_hidden = from.is_hidden,
+			_ => {
+				if let (Some(x), Some(y)) = (from.needs.first(), to.needs.first_mut()) {
+					match self {
+						Field::Role => y.label = x.label.clone(),
+						Field::Headcount => y.count_of_users = x.count_of_users,
+						Field::Begin => y.begin_time = x.begin_time,
+						Field::End => y.end_time = x.end_time,
+						Field::Skills => {
+							let kept = y.skills.clone();
+							y.skills = x.skills.clone();
+							copy_max_years(&kept, &mut y.skills);
+						}
+						Field::Percentage => y.percentage = x.percentage,
+						Field::MaxYears => copy_max_years(&x.skills, &mut y.skills),
+						Field::Name | Field::Visible => {}
+					}
+				}
+			}
+		}
+	}
+}
+
+/// The parts of a need's skills compared between the sides. `max_years` is
+/// compared on its own as `Field::MaxYears`.
+fn skill_keys(need: &HulaProjectStructureNeedData) -> Vec<(&str, Option<&str>, Option<f64>, bool)> {
+	need.skills
+		.iter()
+		.map(|x| {
+			(
+				&x.skill_label[..],
+				x.skillscopelevel_label.as_deref(),
+				x.min_years,
+				x.mandatory,
+			)
+		})
+		.collect()
+}
+
+fn max_years(need: &HulaProjectStructureNeedData) -> Vec<(&str, Option<f64>)> {
+	need.skills
+		.iter()
+		.map(|x| (&x.skill_label[..], x.max_years))
+		.collect()
+}
+
+/// Sets the maximum years of skills in `to` from the skill with the same
+/// label in `from`. Copying the skills of one side must not bring along
+/// that side's maximum years, which Odoo owns.
+fn copy_max_years(
+	from: &[HulaProjectStructureNeedSkillData],
+	to: &mut [HulaProjectStructureNeedSkillData],
+) {
+	for skill in to {
+		if let Some(x) = from.iter().find(|x| x.skill_label == skill.skill_label) {
+			skill.max_years = x.max_years;
+		}
+	}
+}
+
+/// Configuration of the Hula → Odoo direction. Writing to Odoo is enabled
+/// with `ODOO_REVERSE_SYNC=true`; conflicts are detected either way.
+/// `ODOO_FIELD_OWNERSHIP` sets owners per field, e.g.
+/// `name=crm,headcount=hula`. Fields not listed use `last`.
+pub struct ReverseConfig {
+	pub enabled: bool,
+	owners: HashMap<Field, Owner>,
+}
+
+impl ReverseConfig {
+	pub fn from_env() -> ReverseConfig {
+		let enabled = std::env::var("ODOO_REVERSE_SYNC")
+			.map(|v| v == "true" || v == "1")
+			.unwrap_or(false);
+
+		let mut owners = HashMap::new();
+
+		for pair in std::env::var("ODOO_FIELD_OWNERSHIP")
+			.unwrap_or_default()
+			.split(',')
+			.filter(|x| !x.trim().is_empty())
+		{
+			let parsed = pair
+				.split_once('=')
+				.and_then(|(f, o)| Some((Field::parse(f.trim())?, Owner::parse(o.trim())?)))
+				.filter(|(field, _)| !field.crm_owned());
+
+			match parsed {
+				Some((field, owner)) => {
+					owners.insert(field, owner);
+				}
+				None => warn!("Ignoring invalid ODOO_FIELD_OWNERSHIP entry '{}'.", pair),
+			}
+		}
+
+		ReverseConfig { enabled, owners }
+	}
+
+	pub fn owner(&self, field: Field) -> Owner {
+		if field.crm_owned() {
+			return Owner::Crm;
+		}
+
+		self.owners
+			.get(&field)
+			.copied()
+			.unwrap_or(Owner::LastWriter)
+	}
+}
+
+/// One side of a mapped project as seen by a sync run.
+pub struct Side<'a> {
+	pub data: &'a HulaProjectStructureData,
+	/// Whether this side was edited since the last sync.
+	pub changed: bool,
+	pub updated_at: Option<NaiveDateTime>,
+}
+
+/// A field both sides changed to different values since the last sync.
+#[derive(Debug)]
+pub struct Conflict {
+	pub field: Field,
+	/// Side whose value is kept, `None` if the conflict needs a human.
+	pub winner: Option<Owner>,
+}
+
+pub struct Merge {
+	/// The project as both sides should look after the sync.
+	pub data: HulaProjectStructureData,
+	/// Fields where `data` differs from Odoo.
+	pub to_crm: Vec<Field>,
+	/// Fields where `data` differs from Hula.
+	pub to_hula: Vec<Field>,
+	pub conflicts: Vec<Conflict>,
+}
+
+impl Merge {
+	/// Whether a conflict has to be resolved before the project is synced.
+	pub fn is_parked(&self) -> bool {
+		self.conflicts.iter().any(|x| x.winner.is_none())
+	}
+}
+
+/// Decides field by field which side's value is kept. With the `base`
+/// snapshot of the last sync each field is compared three ways, and fields
+/// owned by `last` that both sides changed are conflicts with no winner
+/// unless `resolved` names one. Mappings synced before snapshots were kept
+/// have no `base`; for them the later edit wins. With reverse sync off Odoo
+/// wins every field only Hula changed, and a conflict Hula would win is
+/// parked instead, since its value could not be written to Odoo.
+pub fn merge(
+	config: &ReverseConfig,
+	base: Option<&HulaProjectStructureData>,
+	crm: Side,
+	hula: Side,
+	resolved: &HashMap<Field, Owner>,
+) -> Merge {
+	let mut merged = crm.data.clone();
+	let mut conflicts = vec![];
+
+	for field in Field::ALL.iter().copied() {
+		if field.same(crm.data, hula.data) {
+			continue;
+		}
+
+		let (crm_changed, hula_changed) = match base {
+			Some(base) => (!field.same(base, crm.data), !field.same(base, hula.data)),
+			None => (crm.changed, hula.changed),
+		};
+
+		let winner = match resolved.get(&field) {
+			Some(owner) => Some(*owner),
+			None if !config.enabled && (!crm_changed || !hula_changed) => Some(Owner::Crm),
+			None => match config.owner(field) {
+				Owner::Crm => Some(Owner::Crm),
+				Owner::Hula => Some(Owner::Hula),
+				Owner::LastWriter => match (crm_changed, hula_changed) {
+					(false, true) => Some(Owner::Hula),
+					(true, true) if base.is_some() => None,
+					(true, true) if hula.updated_at > crm.updated_at => Some(Owner::Hula),
+					_ => Some(Owner::Crm),
+				},
+			},
+		};
+		let winner = match winner {
+			Some(Owner::Hula) if !config.enabled => None,
+			other => other,
+		};
+
+		if crm_changed && hula_changed {
+			conflicts.push(Conflict { field, winner });
+		}
+
+		if winner == Some(Owner::Hula) {
+			field.copy(hula.data, &mut merged);
+		}
+	}
+
+	let to_crm = Field::ALL
+		.iter()
+		.copied()
+		.filter(|x| !x.same(&merged, crm.data))
+		.collect();
+	let to_hula = Field::ALL
+		.iter()
+		.copied()
+		.filter(|x| !x.same(&merged, hula.data))
+		.collect();
+
+	Merge {
+		data: merged,
+		to_crm,
+		to_hula,
+		conflicts,
+	}
+}
+
+/// Odoo `crm.lead` values for `fields` of `data`. Skills are passed as a
+/// list and resolved to Odoo records by `odoo_put_leads.py`.
+pub fn lead_values(
+	data: &HulaProjectStructureData,
+	fields: &[Field],
+) -> serde_json::Map<String, serde_json::Value> {
+	use serde_json::json;
+
+	let mut values = serde_json::Map::new();
+	let need = data.needs.first();
+
+	for field in fields {
+		let (key, value) = match (field, need) {
+			(Field::Name, _) => ("x_studio_project_name", json!(data.name)),
+			(Field::Visible, _) => ("x_studio_visible", json!(!data.is_hidden)),
+			(Field::Role, Some(n)) => ("x_studio_role", json!(n.label)),
+			(Field::Headcount, Some(n)) => ("x_studio_nbr_of_positions", json!(n.count_of_users)),
+			(Field::Begin, Some(n)) => ("x_studio_begin", json!(n.begin_time)),
+			(Field::End, Some(n)) => match n.end_time {
+				Some(end) => ("x_studio_end", json!(end)),
+				None => ("x_studio_end", json!(false)),
+			},
+			(Field::Skills, Some(n)) => ("skills", json!(n.skills)),
+			(Field::Percentage, _) | (Field::MaxYears, _) | (_, None) => continue,
+		};
+
+		values.insert(key.to_string(), value);
+	}
+
+	values
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono::NaiveDate;
+
+	fn date() -> NaiveDate {
+		NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()
+	}
+
+	fn project(name: &str, headcount: i32) -> HulaProjectStructureData {
+		HulaProjectStructureData {
+			name: name.to_string(),
+			is_hidden: false,
+			external_id: None,
+			needs: vec![HulaProjectStructureNeedData {
+				label: "Developer".to_string(),
+				count_of_users: headcount,
+				begin_time: date(),
+				end_time: None,
+				percentage: Some(100),
+				skills: vec![],
+			}],
+		}
+	}
+
+	fn config(enabled: bool, owners: &[(Field, Owner)]) -> ReverseConfig {
+		ReverseConfig {
+			enabled,
+			owners: owners.iter().copied().collect(),
+		}
+	}
+
+	fn side(data: &HulaProjectStructureData) -> Side<'_> {
+		Side {
+			data,
+			changed: true,
+			updated_at: None,
+		}
+	}
+
+	#[test]
+	fn clean_merge_takes_each_sides_edit() {
+		let base = project("Portal", 2);
+		let crm = project("Portal", 3);
+		let mut hula = project("Portal", 2);
+		hula.is_hidden = true;
+
+		let merge = merge(
+			&config(true, &[]),
+			Some(&base),
+			side(&crm),
+			side(&hula),
+			&HashMap::new(),
+		);
+
+		assert!(merge.conflicts.is_empty());
+		assert_eq!(merge.data.needs[0].count_of_users, 3);
+		assert!(merge.data.is_hidden);
+		assert_eq!(merge.to_crm, vec![Field::Visible]);
+		assert_eq!(merge.to_hula, vec![Field::Headcount]);
+	}
+
+	#[test]
+	fn conflicting_edits_are_parked() {
+		let base = project("Portal", 2);
+		let crm = project("Portal", 3);
+		let hula = project("Portal", 4);
+
+		let merge = merge(
+			&config(true, &[]),
+			Some(&base),
+			side(&crm),
+			side(&hula),
+			&HashMap::new(),
+		);
+
+		assert!(merge.is_parked());
+		assert_eq!(merge.conflicts.len(), 1);
+		assert_eq!(merge.conflicts[0].field, Field::Headcount);
+	}
+
+	#[test]
+	fn conflicts_are_parked_with_reverse_sync_off() {
+		let base = project("Portal", 2);
+		let crm = project("Portal", 3);
+		let hula = project("Portal", 4);
+
+		let merge = merge(
+			&config(false, &[]),
+			Some(&base),
+			side(&crm),
+			side(&hula),
+			&HashMap::new(),
+		);
+
+		assert!(merge.is_parked());
+	}
+
+	#[test]
+	fn hula_owned_conflicts_are_parked_with_reverse_sync_off() {
+		let base = project("Portal", 2);
+		let crm = project("Portal", 3);
+		let hula = project("Portal", 4);
+
+		let merge = merge(
+			&config(false, &[(Field::Headcount, Owner::Hula)]),
+			Some(&base),
+			side(&crm),
+			side(&hula),
+			&HashMap::new(),
+		);
+
+		assert!(merge.is_parked());
+		assert!(merge.to_crm.is_empty());
+	}
+
+	#[test]
+	fn owner_or_resolution_settles_a_conflict() {
+		let base = project("Portal", 2);
+		let crm = project("Portal", 3);
+		let hula = project("Portal", 4);
+
+		let owned = merge(
+			&config(true, &[(Field::Headcount, Owner::Crm)]),
+			Some(&base),
+			side(&crm),
+			side(&hula),
+			&HashMap::new(),
+		);
+		assert!(!owned.is_parked());
+		assert_eq!(owned.data.needs[0].count_of_users, 3);
+
+		let resolved = [(Field::Headcount, Owner::Hula)].iter().copied().collect();
+		let kept = merge(
+			&config(true, &[]),
+			Some(&base),
+			side(&crm),
+			side(&hula),
+			&resolved,
+		);
+		assert!(!kept.is_parked());
+		assert_eq!(kept.data.needs[0].count_of_users, 4);
+		assert_eq!(kept.to_crm, vec![Field::Headcount]);
+	}
+
+	#[test]
+	fn one_sided_hula_edit_needs_reverse_sync() {
+		let base = project("Portal", 2);
+		let crm = project("Portal", 2);
+		let hula = project("Intranet", 2);
+
+		let two_way = merge(
+			&config(true, &[]),
+			Some(&base),
+			side(&crm),
+			side(&hula),
+			&HashMap::new(),
+		);
+		assert_eq!(two_way.data.name, "Intranet");
+		assert_eq!(two_way.to_crm, vec![Field::Name]);
+		assert!(two_way.to_hula.is_empty());
+
+		let one_way = merge(
+			&config(false, &[]),
+			Some(&base),
+			side(&crm),
+			side(&hula),
+			&HashMap::new(),
+		);
+		assert_eq!(one_way.data.name, "Portal");
+		assert!(one_way.to_crm.is_empty());
+		assert_eq!(one_way.to_hula, vec![Field::Name]);
+	}
+
+	#[test]
+	fn odoo_percentage_and_max_years_reach_hula() {
+		let base = project("Portal", 2);
+		let mut crm = project("Portal", 2);
+		let mut hula = project("Portal", 2);
+		crm.needs[0].percentage = Some(50);
+		hula.needs[0].percentage = Some(80);
+		for data in [&mut crm, &mut hula].iter_mut() {
+			data.needs[0].skills = vec![HulaProjectStructureNeedSkillData {
+				skill_label: "Rust".to_string(),
+				skillscopelevel_label: None,
+				min_years: Some(2.0),
+				max_years: None,
+				mandatory: true,
+			}];
+		}
+		crm.needs[0].skills[0].max_years = Some(5.0);
+		hula.needs[0].skills[0].mandatory = false;
+
+		let merge = merge(
+			&config(true, &[(Field::Skills, Owner::Hula)]),
+			Some(&base),
+			side(&crm),
+			side(&hula),
+			&HashMap::new(),
+		);
+
+		assert!(!merge.is_parked());
+		assert_eq!(merge.data.needs[0].percentage, Some(50));
+		assert_eq!(merge.data.needs[0].skills[0].max_years, Some(5.0));
+		assert!(!merge.data.needs[0].skills[0].mandatory);
+		assert_eq!(merge.to_hula, vec![Field::Percentage, Field::MaxYears]);
+		assert_eq!(merge.to_crm, vec![Field::Skills]);
+	}
+
+	#[test]
+	fn later_edit_wins_without_a_snapshot() {
+		let crm = project("Portal", 3);
+		let hula = project("Portal", 4);
+		let earlier = date().and_hms_opt(8, 0, 0).unwrap();
+		let later = date().and_hms_opt(9, 0, 0).unwrap();
+
+		let merge = merge(
+			&config(true, &[]),
+			None,
+			Side {
+				data: &crm,
+				changed: true,
+				updated_at: Some(earlier),
+			},
+			Side {
+				data: &hula,
+				changed: true,
+				updated_at: Some(later),
+			},
+			&HashMap::new(),
+		);
+
+		assert!(!merge.is_parked());
+		assert_eq!(merge.data.needs[0].count_of_users, 4);
+	}
+}
@@ -0,0 +1,284 @@
+use chrono::NaiveDate;
+
+use super::odoo_module::HulaProjectStructureData;
+use crate::hulautils::{Skill, SkillScopeLevel};
+
+/// Something in a project structure that Hula would reject or misread.
+/// Needs are numbered from 1 as on the lead.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+	EmptyName,
+	EmptyRole {
+		need: usize,
+	},
+	InvalidHeadcount {
+		need: usize,
+		count: i32,
+	},
+	InvalidPercentage {
+		need: usize,
+		percentage: i32,
+	},
+	EndBeforeBegin {
+		need: usize,
+		begin: NaiveDate,
+		end: NaiveDate,
+	},
+	UnknownSkill {
+		need: usize,
+		skill: String,
+	},
+	MaxYearsBelowMin {
+		need: usize,
+		skill: String,
+	},
+	UnknownLevel {
+		need: usize,
+		skill: String,
+		level: String,
+	},
+}
+
+impl std::fmt::Display for Problem {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			Problem::EmptyName => write!(f, "Project name is empty"),
+			Problem::EmptyRole { need } => write!(f, "Need {}: role is empty", need),
+			Problem::InvalidHeadcount { need, count } => {
+				write!(
+					f,
+					"Need {}: number of positions is {}, must be at least 1",
+					need, count
+				)
+			}
+			Problem::InvalidPercentage { need, percentage } => {
+				write!(
+					f,
+					"Need {}: percentage is {}, must be 1-100",
+					need, percentage
+				)
+			}
+			Problem::EndBeforeBegin { need, begin, end } => {
+				write!(f, "Need {}: end {} is before begin {}", need, end, begin)
+			}
+			Problem::UnknownSkill { need, skill } => {
+				write!(f, "Need {}: skill '{}' is not in Hula", need, skill)
+			}
+			Problem::MaxYearsBelowMin { need, skill } => write!(
+				f,
+				"Need {}: maximum years of skill '{}' is below its minimum",
+				need, skill
+			),
+			Problem::UnknownLevel { need, skill, level } => write!(
+				f,
+				"Need {}: level '{}' of skill '{}' is not in Hula",
+				need, level, skill
+			),
+		}
+	}
+}
+
+/// Checks a structure before it is sent to Hula. Skills and levels are only
+/// checked if Hula returned any.
+pub fn validate(
+	data: &HulaProjectStructureData,
+	skills: &[Skill],
+	levels: &[SkillScopeLevel],
+) -> Vec<Problem> {
+	let mut problems = vec![];
+
+	if data.name.trim().is_empty() {
+		problems.push(Problem::EmptyName);
+	}
+
+	for (idx, need) in data.needs.iter().enumerate() {
+		let n = idx + 1;
+
+		if need.label.trim().is_empty() {
+			problems.push(Problem::EmptyRole { need: n });
+		}
+
+		if need.count_of_users < 1 {
+			problems.push(Problem::InvalidHeadcount {
+				need: n,
+				count: need.count_of_users,
+			});
+		}
+
+		if let Some(percentage) = need.percentage {
+			if !(1..=100).contains(&percentage) {
+				problems.push(Problem::InvalidPercentage {
+					need: n,
+					percentage,
+				});
+			}
+		}
+
+		if let Some(end) = need.end_time {
+			if end < need.begin_time {
+				problems.push(Problem::EndBeforeBegin {
+					need: n,
+					begin: need.begin_time,
+					end,
+				});
+			}
+		}
+
+		for skill in &need.skills {
+			if !skills.is_empty() && !skills.iter().any(|x| x.label == skill.skill_label) {
+				problems.push(Problem::UnknownSkill {
+					need: n,
+					skill: skill.skill_label.clone(),
+				});
+			}
+
+			if let (Some(min), Some(max)) = (skill.min_years, skill.max_years) {
+				if max < min {
+					problems.push(Problem::MaxYearsBelowMin {
+						need: n,
+						skill: skill.skill_label.clone(),
+					});
+				}
+			}
+
+			if let Some(level) = &skill.skillscopelevel_label {
+				if !levels.is_empty() && !levels.iter().any(|x| &x.label == level) {
+					problems.push(Problem::UnknownLevel {
+						need: n,
+						skill: skill.skill_label.clone(),
+						level: level.clone(),
+					});
+				}
+			}
+		}
+	}
+
+	problems
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::modules::odoo::odoo_module::{
+		HulaProjectStructureNeedData, HulaProjectStructureNeedSkillData,
+	};
+
+	fn date(month: u32, day: u32) -> NaiveDate {
+		NaiveDate::from_ymd_opt(2024, month, day).unwrap()
+	}
+
+	fn skill(label: &str, level: Option<&str>) -> HulaProjectStructureNeedSkillData {
+		HulaProjectStructureNeedSkillData {
+			skill_label: label.to_string(),
+			skillscopelevel_label: level.map(|x| x.to_string()),
+			min_years: Some(2.0),
+			max_years: None,
+			mandatory: true,
+		}
+	}
+
+	fn need(skills: Vec<HulaProjectStructureNeedSkillData>) -> HulaProjectStructureNeedData {
+		HulaProjectStructureNeedData {
+			label: "Developer".to_string(),
+			count_of_users: 1,
+			begin_time: date(1, 1),
+			end_time: Some(date(6, 30)),
+			percentage: Some(100),
+			skills,
+		}
+	}
+
+	fn project(needs: Vec<HulaProjectStructureNeedData>) -> HulaProjectStructureData {
+		HulaProjectStructureData {
+			name: "Portal".to_string(),
+			is_hidden: false,
+			external_id: None,
+			needs,
+		}
+	}
+
+	fn catalogue() -> (Vec<Skill>, Vec<SkillScopeLevel>) {
+		let skills = vec![Skill {
+			id: uuid::Uuid::new_v4(),
+			label: "Rust".to_string(),
+			aliases: vec![],
+		}];
+		let levels = vec![SkillScopeLevel {
+			id: uuid::Uuid::new_v4(),
+			label: "Senior".to_string(),
+		}];
+		(skills, levels)
+	}
+
+	#[test]
+	fn valid_project_has_no_problems() {
+		let (skills, levels) = catalogue();
+		let data = project(vec![need(vec![skill("Rust", Some("Senior"))])]);
+
+		assert!(validate(&data, &skills, &levels).is_empty());
+	}
+
+	#[test]
+	fn unknown_skill_and_level() {
+		let (skills, levels) = catalogue();
+		let data = project(vec![need(vec![
+			skill("Cobol", None),
+			skill("Rust", Some("Guru")),
+		])]);
+
+		assert_eq!(
+			validate(&data, &skills, &levels),
+			vec![
+				Problem::UnknownSkill {
+					need: 1,
+					skill: "Cobol".to_string(),
+				},
+				Problem::UnknownLevel {
+					need: 1,
+					skill: "Rust".to_string(),
+					level: "Guru".to_string(),
+				},
+			]
+		);
+	}
+
+	#[test]
+	fn skills_are_not_checked_without_a_catalogue() {
+		let data = project(vec![need(vec![skill("Cobol", Some("Guru"))])]);
+
+		assert!(validate(&data, &[], &[]).is_empty());
+	}
+
+	#[test]
+	fn end_before_begin() {
+		let mut item = need(vec![]);
+		item.end_time = Some(date(1, 1));
+		item.begin_time = date(2, 1);
+		let data = project(vec![need(vec![]), item]);
+
+		assert_eq!(
+			validate(&data, &[], &[]),
+			vec![Problem::EndBeforeBegin {
+				need: 2,
+				begin: date(2, 1),
+				end: date(1, 1),
+			}]
+		);
+	}
+
+	#[test]
+	fn percentage_out_of_range() {
+		for percentage in &[0, 101] {
+			let mut item = need(vec![]);
+			item.percentage = Some(*percentage);
+
+			assert_eq!(
+				validate(&project(vec![item]), &[], &[]),
+				vec![Problem::InvalidPercentage {
+					need: 1,
+					percentage: *percentage,
+				}]
+			);
+		}
+	}
+}
@@ -0,0 +1,107 @@
+use log::warn;
+
+/// What to do with a mapping row whose Hula project no longer exists.
+/// Configured with the `ORPHAN_POLICY` environment variable.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OrphanPolicy {
+	/// Log the orphan and leave the mapping untouched.
+	Ignore,
+	/// Keep the mapping row and create a new Hula project for it.
+	Recreate,
+	/// Delete the mapping row so the CRM record is imported as new.
+	Reimport,
+	/// Mark the mapping as intentionally removed and stop syncing it.
+	Remove,
+}
+
+impl OrphanPolicy {
+	pub fn from_env() -> OrphanPolicy {
+		let value = std::env::var("ORPHAN_POLICY").unwrap_or_default();
+
+		match &value.to_lowercase()[..] {
+			"" | "ignore" => OrphanPolicy::Ignore,
+			"recreate" => OrphanPolicy::Recreate,
+			"reimport" => OrphanPolicy::Reimport,
+			"remove" => OrphanPolicy::Remove,
+			other => {
+				warn!(
+					"Unknown ORPHAN_POLICY '{}', ignoring orphaned mappings.",
+					other
+				);
+				OrphanPolicy::Ignore
+			}
+		}
+	}
+
+	/// Whether applying the policy requires the CRM record to be fetched
+	/// again even if it has not changed.
+	pub fn needs_refetch(&self) -> bool {
+		matches!(self, OrphanPolicy::Recreate | OrphanPolicy::Reimport)
+	}
+}
+
+/// Largest share of the synced mappings that may turn up orphaned in one
+/// run. `ORPHAN_MAX_FRACTION`, default 0.5.
+fn max_fraction() -> f64 {
+	std::env::var("ORPHAN_MAX_FRACTION")
+		.ok()
+		.and_then(|v| v.parse().ok())
+		.filter(|v: &f64| (0.0..=1.0).contains(v))
+		.unwrap_or(0.5)
+}
+
+/// Whether `orphaned` of `synced` mappings looks like a bad answer from
+/// Hula rather than deleted projects: Hula listed no projects at all, or
+/// more than the allowed share of mappings lost their project at once.
+/// A single orphan is always believed.
+fn implausible(projects: usize, synced: usize, orphaned: usize, fraction: f64) -> bool {
+	if orphaned == 0 {
+		return false;
+	}
+
+	projects == 0 || (orphaned > 1 && orphaned as f64 > synced as f64 * fraction)
+}
+
+/// Checks the orphans of `source` before a policy is applied to them.
+/// Returns the policy to use, `Ignore` if the orphans are implausible.
+pub fn guard(
+	policy: OrphanPolicy,
+	source: &str,
+	projects: usize,
+	synced: usize,
+	orphaned: usize,
+) -> OrphanPolicy {
+	if policy == OrphanPolicy::Ignore || !implausible(projects, synced, orphaned, max_fraction()) {
+		return policy;
+	}
+
+	warn!(
+		"{} of {} {} mappings look orphaned with {} Hula projects listed, not applying policy {:?} this run.",
+		orphaned, synced, source, projects, policy
+	);
+
+	OrphanPolicy::Ignore
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn empty_project_list_is_implausible() {
+		assert!(implausible(0, 10, 10, 0.5));
+		assert!(implausible(0, 1, 1, 0.5));
+	}
+
+	#[test]
+	fn mass_orphaning_is_implausible() {
+		assert!(implausible(40, 10, 6, 0.5));
+		assert!(!implausible(40, 10, 5, 0.5));
+	}
+
+	#[test]
+	fn single_orphan_is_believed() {
+		assert!(!implausible(40, 1, 1, 0.5));
+		assert!(!implausible(40, 10, 0, 0.5));
+	}
+}
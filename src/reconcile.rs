@@ -0,0 +1,369 @@
+use log::error;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+
+use crate::changeset::Mapping;
+use crate::hulautils;
+use crate::hulautils::HulaProject;
+use crate::models::odoo_project::Pool;
+use crate::models::sync_status;
+use crate::modules::hubspot::hubspot_module;
+use crate::modules::odoo::odoo_module;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+	/// CRM record that has no mapping and therefore no Hula project.
+	UnmappedCrm,
+	/// Hula project that no mapping points to.
+	UnmappedHula,
+	/// Mapping whose Hula project no longer exists.
+	OrphanedMapping,
+	/// Mapping whose CRM record is no longer returned by the CRM.
+	StaleMapping,
+	/// Mapping reserved before project creation and never confirmed.
+	PendingMapping,
+	/// Names differ between the CRM record and the Hula project.
+	NameDrift,
+	/// Several Hula projects share a name, a CRM record has several
+	/// mapping rows, or several CRM records map to one Hula project.
+	Duplicate,
+}
+
+impl Category {
+	const ALL: [Category; 7] = [
+		Category::UnmappedCrm,
+		Category::UnmappedHula,
+		Category::OrphanedMapping,
+		Category::StaleMapping,
+		Category::PendingMapping,
+		Category::NameDrift,
+		Category::Duplicate,
+	];
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Category::UnmappedCrm => "unmapped_crm",
+			Category::UnmappedHula => "unmapped_hula",
+			Category::OrphanedMapping => "orphaned_mapping",
+			Category::StaleMapping => "stale_mapping",
+			Category::PendingMapping => "pending_mapping",
+			Category::NameDrift => "name_drift",
+			Category::Duplicate => "duplicate",
+		}
+	}
+
+	fn parse(name: &str) -> Option<Category> {
+		Category::ALL.iter().copied().find(|x| x.name() == name)
+	}
+
+	/// Categories `--fix` can repair. Unmapped CRM records are created by the
+	/// regular sync, the rest need a human.
+	fn is_fixable(&self) -> bool {
+		matches!(
+			self,
+			Category::OrphanedMapping
+				| Category::StaleMapping
+				| Category::PendingMapping
+				| Category::NameDrift
+		)
+	}
+}
+
+#[derive(Serialize, Debug)]
+pub struct Issue {
+	pub category: Category,
+	pub source: &'static str,
+	pub crm_id: Option<String>,
+	pub hula_id: Option<String>,
+	pub detail: String,
+	pub fixed: bool,
+}
+
+/// What a CRM module found while comparing its records, its mapping table
+/// and the Hula projects.
+pub struct SourceReport {
+	pub issues: Vec<Issue>,
+	pub mapped_hula_ids: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Report {
+	pub issues: Vec<Issue>,
+}
+
+struct Options {
+	json: bool,
+	fix: Vec<Category>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+	let mut options = Options {
+		json: false,
+		fix: vec![],
+	};
+
+	let mut iter = args.iter();
+
+	while let Some(arg) = iter.next() {
+		match &arg[..] {
+			"--json" => options.json = true,
+			"--fix" => {
+				let value = match iter.next() {
+					Some(v) => v,
+					None => {
+						return Err("--fix needs a comma separated list of categories".to_string())
+					}
+				};
+
+				for name in value.split(',') {
+					let category = match Category::parse(name.trim()) {
+						Some(c) => c,
+						None => return Err(format!("Unknown category: {}", name)),
+					};
+
+					if !category.is_fixable() {
+						return Err(format!("Category {} cannot be fixed automatically", name));
+					}
+
+					options.fix.push(category);
+				}
+			}
+			other => return Err(format!("Unknown argument: {}", other)),
+		}
+	}
+
+	Ok(options)
+}
+
+/// Entry point of `hula-sync reconcile [--json] [--fix <category,...>]`.
+pub async fn run(pool: Pool, args: &[String]) -> Result<(), String> {
+	let options = parse_args(args)?;
+
+	let config = hulautils::get_config().await?;
+
+	let result = build_report(&config, &pool, &options.fix).await;
+
+	if let Err(e) = hulautils::close_config(&config).await {
+		error!("NO CONNECTION to HULA: {}", &e);
+	}
+
+	let report = result?;
+
+	if options.json {
+		match serde_json::to_string_pretty(&report) {
+			Ok(json) => println!("{}", json),
+			Err(e) => return Err(format!("Serde failed: {}", e)),
+		}
+	} else {
+		print_table(&report);
+	}
+
+	Ok(())
+}
+
+async fn build_report(
+	config: &hulautils::HulaConfig,
+	pool: &Pool,
+	fix: &[Category],
+) -> Result<Report, String> {
+	let projects = hulautils::get_hula_projects(config).await?;
+
+	let modules = std::env::var("MODULES").unwrap_or_default();
+
+	let mut issues = vec![];
+	let mut mapped_hula_ids = vec![];
+
+	for module in modules.split(',') {
+		let source = match module {
+			"odoo" => odoo_module::reconcile(config, pool, &projects, fix).await?,
+			"hubspot" => hubspot_module::reconcile(config, pool, &projects, fix).await?,
+			_ => continue,
+		};
+
+		issues.extend(source.issues);
+		mapped_hula_ids.extend(source.mapped_hula_ids);
+	}
+
+	issues.extend(find_unmapped_hula(&projects, &mapped_hula_ids));
+	issues.extend(find_duplicates(&projects));
+
+	Ok(Report { issues })
+}
+
+fn find_unmapped_hula(projects: &[HulaProject], mapped_hula_ids: &[String]) -> Vec<Issue> {
+	let mapped: HashSet<&String> = mapped_hula_ids.iter().collect();
+
+	projects
+		.iter()
+		.filter(|x| !mapped.contains(&x.id))
+		.map(|x| Issue {
+			category: Category::UnmappedHula,
+			source: "hula",
+			crm_id: None,
+			hula_id: Some(x.id.clone()),
+			detail: format!("No CRM record for project '{}'", &x.name),
+			fixed: false,
+		})
+		.collect()
+}
+
+fn find_duplicates(projects: &[HulaProject]) -> Vec<Issue> {
+	let mut by_name: HashMap<String, Vec<&HulaProject>> = HashMap::new();
+
+	for project in projects {
+		by_name
+			.entry(project.name.trim().to_lowercase())
+			.or_default()
+			.push(project);
+	}
+
+	let mut issues = vec![];
+
+	for same in by_name.values().filter(|x| x.len() > 1) {
+		for project in same {
+			issues.push(Issue {
+				category: Category::Duplicate,
+				source: "hula",
+				crm_id: None,
+				hula_id: Some(project.id.clone()),
+				detail: format!("{} projects are named '{}'", same.len(), &project.name),
+				fixed: false,
+			});
+		}
+	}
+
+	issues
+}
+
+/// Mapping rows of `source` that share a CRM record or a Hula project.
+/// Removed rows are left out, they no longer sync.
+pub fn find_duplicate_mappings<M>(source: &'static str, log: &[M]) -> Vec<Issue>
+where
+	M: Mapping,
+	M::Key: Display,
+{
+	let mut by_crm: HashMap<M::Key, Vec<&M>> = HashMap::new();
+	let mut by_hula: HashMap<uuid::Uuid, Vec<&M>> = HashMap::new();
+
+	for row in log
+		.iter()
+		.filter(|x| x.sync_status() != sync_status::REMOVED)
+	{
+		by_crm.entry(row.crm_key()).or_default().push(row);
+		if let Some(id) = row.hula_id() {
+			by_hula.entry(id).or_default().push(row);
+		}
+	}
+
+	let mut issues = vec![];
+
+	for (key, rows) in by_crm.iter().filter(|(_, x)| x.len() > 1) {
+		for row in rows {
+			issues.push(Issue {
+				category: Category::Duplicate,
+				source,
+				crm_id: Some(key.to_string()),
+				hula_id: row.hula_id().map(|x| x.to_string()),
+				detail: format!("{} mapping rows for this record", rows.len()),
+				fixed: false,
+			});
+		}
+	}
+
+	for (id, rows) in by_hula.iter().filter(|(_, x)| x.len() > 1) {
+		for row in rows {
+			issues.push(Issue {
+				category: Category::Duplicate,
+				source,
+				crm_id: Some(row.crm_key().to_string()),
+				hula_id: Some(id.to_string()),
+				detail: format!("{} records are mapped to this project", rows.len()),
+				fixed: false,
+			});
+		}
+	}
+
+	issues
+}
+
+fn print_table(report: &Report) {
+	if report.issues.is_empty() {
+		println!("No mismatches found.");
+		return;
+	}
+
+	println!(
+		"{:<17} {:<8} {:<12} {:<37} {:<5} DETAIL",
+		"CATEGORY", "SOURCE", "CRM ID", "HULA ID", "FIXED"
+	);
+
+	for issue in &report.issues {
+		println!(
+			"{:<17} {:<8} {:<12} {:<37} {:<5} {}",
+			issue.category.name(),
+			issue.source,
+			issue.crm_id.as_deref().unwrap_or("-"),
+			issue.hula_id.as_deref().unwrap_or("-"),
+			if issue.fixed { "yes" } else { "no" },
+			&issue.detail
+		);
+	}
+
+	println!();
+
+	for category in Category::ALL.iter() {
+		let count = report
+			.issues
+			.iter()
+			.filter(|x| x.category == *category)
+			.count();
+
+		if count > 0 {
+			println!("{}: {}", category.name(), count);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::changeset::testing::row;
+	use uuid::Uuid;
+
+	#[test]
+	fn flags_several_rows_for_one_record() {
+		let log = vec![
+			row(1, Some(Uuid::new_v4())),
+			row(1, Some(Uuid::new_v4())),
+			row(2, Some(Uuid::new_v4())),
+		];
+
+		let issues = find_duplicate_mappings("odoo", &log);
+
+		assert_eq!(issues.len(), 2);
+		assert!(issues.iter().all(|x| x.crm_id.as_deref() == Some("1")));
+	}
+
+	#[test]
+	fn flags_several_records_for_one_project() {
+		let shared = Uuid::new_v4();
+		let log = vec![row(1, Some(shared)), row(2, Some(shared)), row(3, None)];
+
+		let issues = find_duplicate_mappings("odoo", &log);
+
+		assert_eq!(issues.len(), 2);
+		assert!(issues.iter().all(|x| x.hula_id == Some(shared.to_string())));
+	}
+
+	#[test]
+	fn ignores_removed_rows() {
+		let shared = Uuid::new_v4();
+		let mut removed = row(1, Some(shared));
+		removed.status = sync_status::REMOVED;
+		let log = vec![removed, row(1, Some(shared))];
+
+		assert!(find_duplicate_mappings("odoo", &log).is_empty());
+	}
+}
@@ -0,0 +1,160 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::db;
+use crate::models::odoo_project::Pool;
+use crate::models::sync_retry::SyncRetry;
+
+/// Delay before the first retry, doubled on each further failure up to
+/// the maximum. `SYNC_RETRY_DELAY` and `SYNC_RETRY_MAX_DELAY`, in seconds.
+fn delay(attempts: i32) -> chrono::Duration {
+	let base: i64 = std::env::var("SYNC_RETRY_DELAY")
+		.ok()
+		.and_then(|v| v.parse().ok())
+		.unwrap_or(60);
+	let max: i64 = std::env::var("SYNC_RETRY_MAX_DELAY")
+		.ok()
+		.and_then(|v| v.parse().ok())
+		.unwrap_or(6 * 60 * 60);
+
+	backoff(attempts, base, max)
+}
+
+/// `base` seconds doubled for each attempt after the first, at most `max`.
+fn backoff(attempts: i32, base: i64, max: i64) -> chrono::Duration {
+	let factor = 1_i64 << (attempts - 1).clamp(0, 20);
+	chrono::Duration::seconds(base.saturating_mul(factor).min(max))
+}
+
+/// Records of `source` waiting for a retry.
+pub async fn load(pool: &Pool, source: &str) -> Result<Vec<SyncRetry>, String> {
+	use crate::schema::sync_retries::dsl;
+
+	let source = source.to_string();
+	let items = db::run(pool, move |conn| {
+		dsl::sync_retries
+			.filter(dsl::source.eq(source))
+			.load::<SyncRetry>(conn)
+	})
+	.await?;
+
+	match items {
+		Ok(items) => Ok(items),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+/// Whether the retry of `item` is due at `now`.
+pub fn is_due(item: &SyncRetry, now: NaiveDateTime) -> bool {
+	item.next_attempt_at <= now
+}
+
+/// Counts a failed attempt and schedules the next one. `payload` is what
+/// was sent to Hula, if the record got that far. `rejected` counts the
+/// attempt as rejected too, see `SyncRetry::rejections`.
+pub async fn record_failure(
+	pool: &Pool,
+	source: &str,
+	crm_id: String,
+	payload: Option<String>,
+	error: String,
+	rejected: bool,
+) -> Result<SyncRetry, String> {
+	use crate::schema::sync_retries::dsl;
+
+	let source = source.to_string();
+	let result = db::run(pool, move |conn| {
+		let existing = dsl::sync_retries
+			.filter(dsl::source.eq(&source))
+			.filter(dsl::crm_id.eq(&crm_id))
+			.first::<SyncRetry>(conn)
+			.optional()?;
+
+		let now = chrono::Utc::now().naive_utc();
+		let rejection = i32::from(rejected);
+
+		match existing {
+			Some(item) => {
+				let attempts = item.attempts + 1;
+				diesel::update(dsl::sync_retries.find(item.id))
+					.set((
+						dsl::attempts.eq(attempts),
+						dsl::next_attempt_at.eq(now + delay(attempts)),
+						dsl::last_error.eq(error),
+						dsl::payload.eq(payload),
+						dsl::rejections.eq(item.rejections + rejection),
+					))
+					.get_result::<SyncRetry>(conn)
+			}
+			None => {
+				let item = SyncRetry {
+					id: uuid::Uuid::new_v4(),
+					source,
+					crm_id,
+					attempts: 1,
+					next_attempt_at: now + delay(1),
+					last_error: error,
+					updated_by: "hulasync".to_string(),
+					updated_at: now,
+					payload,
+					rejections: rejection,
+				};
+				diesel::insert_into(dsl::sync_retries)
+					.values(&item)
+					.get_result::<SyncRetry>(conn)
+			}
+		}
+	})
+	.await?;
+
+	match result {
+		Ok(item) => Ok(item),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+/// Forgets the retries of records that have synced.
+pub async fn clear(pool: &Pool, source: &str, crm_ids: Vec<String>) -> Result<(), String> {
+	use crate::schema::sync_retries::dsl;
+
+	if crm_ids.is_empty() {
+		return Ok(());
+	}
+
+	let source = source.to_string();
+	let deleted = db::run(pool, move |conn| {
+		diesel::delete(
+			dsl::sync_retries
+				.filter(dsl::source.eq(source))
+				.filter(dsl::crm_id.eq_any(crm_ids)),
+		)
+		.execute(conn)
+	})
+	.await?;
+
+	match deleted {
+		Ok(_) => Ok(()),
+		Err(e) => Err(format!("failed: {}", e)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn backoff_doubles_up_to_the_cap() {
+		let delays: Vec<i64> = (1..=5).map(|x| backoff(x, 60, 300).num_seconds()).collect();
+
+		assert_eq!(delays, vec![60, 120, 240, 300, 300]);
+	}
+
+	#[test]
+	fn backoff_does_not_overflow() {
+		let day = 24 * 60 * 60;
+
+		assert_eq!(backoff(1000, 60, day).num_seconds(), day);
+		assert_eq!(backoff(40, i64::MAX / 2, day).num_seconds(), day);
+		assert_eq!(backoff(0, 60, 300).num_seconds(), 60);
+	}
+}
@@ -1,20 +1,26 @@
 table! {
 	hubspot_projects (id) {
 		id -> Uuid,
-		hula_id -> Uuid,
+		hula_id -> Nullable<Uuid>,
 		hubspot_id -> Varchar,
 		name -> Varchar,
 		updated_by -> Varchar,
+		sync_status -> Varchar,
 	}
 }
 
 table! {
 	odoo_projects (id) {
 		id -> Uuid,
-		hula_id -> Uuid,
+		hula_id -> Nullable<Uuid>,
 		odoo_id -> Int4,
 		name -> Varchar,
 		updated_by -> Varchar,
+		sync_status -> Varchar,
+		content_hash -> Nullable<Varchar>,
+		summary_hash -> Nullable<Varchar>,
+		synced_at -> Nullable<Timestamp>,
+		snapshot -> Nullable<Text>,
 	}
 }
 
@@ -50,4 +56,49 @@ table! {
 	}
 }
 
+table! {
+	sync_conflicts (id) {
+		id -> Uuid,
+		source -> Varchar,
+		mapping_id -> Uuid,
+		crm_id -> Varchar,
+		hula_id -> Uuid,
+		field -> Varchar,
+		crm_value -> Text,
+		hula_value -> Text,
+		status -> Varchar,
+		updated_by -> Varchar,
+		updated_at -> Timestamp,
+	}
+}
+
+table! {
+	sync_retries (id) {
+		id -> Uuid,
+		source -> Varchar,
+		crm_id -> Varchar,
+		attempts -> Int4,
+		next_attempt_at -> Timestamp,
+		last_error -> Text,
+		updated_by -> Varchar,
+		updated_at -> Timestamp,
+		payload -> Nullable<Text>,
+		rejections -> Int4,
+	}
+}
+
+table! {
+	sync_dead_letter (id) {
+		id -> Uuid,
+		source -> Varchar,
+		crm_id -> Varchar,
+		payload -> Text,
+		error -> Text,
+		attempts -> Int4,
+		status -> Varchar,
+		updated_by -> Varchar,
+		updated_at -> Timestamp,
+	}
+}
+
 allow_tables_to_appear_in_same_query!(hubspot_projects, odoo_projects,);
@@ -0,0 +1,82 @@
+use log::{info, warn};
+use std::future::Future;
+use std::time::Duration;
+use tokio::sync::watch;
+
+pub type Shutdown = watch::Receiver<bool>;
+
+/// Installs SIGINT/SIGTERM handlers. The returned receiver flips to `true`
+/// once either signal has been received.
+pub fn listen() -> Shutdown {
+	let (tx, rx) = watch::channel(false);
+
+	tokio::spawn(async move {
+		wait_for_signal().await;
+		info!("Shutdown signal received.");
+		let _ = tx.send(true);
+	});
+
+	rx
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+	use tokio::signal::unix::{signal, SignalKind};
+
+	let mut term = match signal(SignalKind::terminate()) {
+		Ok(s) => s,
+		Err(e) => {
+			warn!("Could not install SIGTERM handler: {}", e);
+			let _ = tokio::signal::ctrl_c().await;
+			return;
+		}
+	};
+
+	tokio::select! {
+		_ = tokio::signal::ctrl_c() => {}
+		_ = term.recv() => {}
+	}
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+	let _ = tokio::signal::ctrl_c().await;
+}
+
+pub fn is_requested(shutdown: &Shutdown) -> bool {
+	*shutdown.borrow()
+}
+
+/// Resolves when shutdown has been requested.
+pub async fn requested(shutdown: &mut Shutdown) {
+	while !is_requested(shutdown) {
+		if shutdown.changed().await.is_err() {
+			// Sender gone, no signal can arrive any more.
+			futures::future::pending::<()>().await;
+		}
+	}
+}
+
+/// Runs `work` to completion. If shutdown is requested meanwhile, the work is
+/// given `grace` more time to finish before it is dropped.
+pub async fn run_with_grace<F, T>(
+	work: F,
+	shutdown: &mut Shutdown,
+	grace: Duration,
+) -> Result<T, String>
+where
+	F: Future<Output = Result<T, String>>,
+{
+	tokio::pin!(work);
+
+	tokio::select! {
+		result = &mut work => result,
+		_ = requested(shutdown) => {
+			info!("Waiting up to {} seconds for the current module to finish.", grace.as_secs());
+			match tokio::time::timeout(grace, work).await {
+				Ok(result) => result,
+				Err(_) => Err("Module did not finish before the shutdown timeout".to_string()),
+			}
+		}
+	}
+}